@@ -0,0 +1,219 @@
+//! The email guardian's contract args.
+//!
+//! Version 0, untagged:
+//!
+//! ```text
+//! 0x00 ‖ subjectIndex ‖ subjectRightIndex ‖ fromIndex ‖ fromLeftIndex ‖
+//! fromRightIndex ‖ dkimHeaderIndex ‖ selectorIndex ‖ selectorRightIndex ‖
+//! sdidIndex ‖ sdidRightIndex ‖ len ‖ emailHeader ‖ len ‖ dkimSig
+//! ```
+//!
+//! Version 1 adds the tag and appends the Subject segments and an optional
+//! body section:
+//!
+//! ```text
+//! 0x00 0x01 ‖ <version 0 fields after 0x00> ‖ segmentCount ‖
+//! (left ‖ right)* ‖ hasBody: u8 ‖
+//! [bodyHashIndex ‖ bodyLength ‖ recoveryHashIndex ‖ len ‖ body]
+//! ```
+//!
+//! All integers are big-endian `u32` unless noted. The second byte of
+//! version 0 args is the high byte of `subjectIndex`, which is zero for any
+//! header under 16 MiB, so a zero there identifies the untagged layout.
+
+use utils::args::{args_tag, put_bytes, put_u32, ArgsReader, GuardianType};
+
+use crate::{error::ParserError, types::DkimParams, ParserResult};
+
+/// The layout `pack_args` emits by default.
+pub const ARGS_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BodyArgs {
+    /// Offset of the `bh=` value in the header.
+    pub body_hash_index: usize,
+    /// The `l=` tag, or the canonicalized body length without one.
+    pub body_length: usize,
+    /// Offset of the recovery hash in `body`.
+    pub recovery_hash_index: usize,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnpackedArgs {
+    pub version: u8,
+    pub subject_index: usize,
+    pub subject_right_index: usize,
+    pub from_index: usize,
+    pub from_left_index: usize,
+    pub from_right_index: usize,
+    pub dkim_header_index: usize,
+    pub selector_index: usize,
+    pub selector_right_index: usize,
+    pub sdid_index: usize,
+    pub sdid_right_index: usize,
+    pub email_header: Vec<u8>,
+    pub dkim_sig: Vec<u8>,
+    /// Always empty in version 0.
+    pub subject_segments: Vec<(usize, usize)>,
+    pub body: Option<BodyArgs>,
+}
+
+/// Packs `params` in the layout of `version`. With `recovery_hash_index` the
+/// body section is included, which version 0 can't carry.
+pub fn pack_args(
+    params: &DkimParams,
+    recovery_hash_index: Option<usize>,
+    version: u8,
+) -> ParserResult<Vec<u8>> {
+    let mut out = match version {
+        0 if recovery_hash_index.is_some() => {
+            return Err(ParserError::InvalidArgs(
+                "version 0 args have no body section".to_string(),
+            ))
+        }
+        0 => vec![GuardianType::Email as u8],
+        1 => args_tag(GuardianType::Email, version).to_vec(),
+        _ => {
+            return Err(ParserError::InvalidArgs(format!(
+                "unknown args version: {}",
+                version
+            )))
+        }
+    };
+
+    for index in [
+        params.subject_index,
+        params.subject_right_index,
+        params.from_index,
+        params.from_left_index,
+        params.from_right_index,
+        params.dkim_header_index,
+        params.selector_index,
+        params.selector_right_index,
+        params.sdid_index,
+        params.sdid_right_index,
+    ] {
+        put_u32(&mut out, index);
+    }
+    put_bytes(&mut out, &params.email_header);
+    put_bytes(&mut out, &params.dkim_sig);
+    if version == 0 {
+        return Ok(out);
+    }
+
+    put_u32(&mut out, params.subject_segments.len());
+    for (left, right) in &params.subject_segments {
+        put_u32(&mut out, *left);
+        put_u32(&mut out, *right);
+    }
+    match recovery_hash_index {
+        Some(recovery_hash_index) => {
+            out.push(1);
+            put_u32(&mut out, params.body_hash_index);
+            put_u32(&mut out, params.body_length.unwrap_or(params.body.len()));
+            put_u32(&mut out, recovery_hash_index);
+            put_bytes(&mut out, &params.body);
+        }
+        None => out.push(0),
+    }
+    Ok(out)
+}
+
+/// Decodes args of any version.
+pub fn unpack_args(args: &[u8]) -> ParserResult<UnpackedArgs> {
+    unpack(args).map_err(|e| ParserError::InvalidArgs(e.to_string()))
+}
+
+fn unpack(args: &[u8]) -> anyhow::Result<UnpackedArgs> {
+    let mut reader = ArgsReader::new(args);
+    let version = if args.get(1) == Some(&0) {
+        if reader.read_u8()? != GuardianType::Email as u8 {
+            anyhow::bail!("not email args");
+        }
+        0
+    } else {
+        reader.read_tag(GuardianType::Email)?
+    };
+    if version > ARGS_VERSION {
+        anyhow::bail!("unknown args version: {}", version);
+    }
+
+    let mut unpacked = UnpackedArgs {
+        version,
+        subject_index: reader.read_u32()?,
+        subject_right_index: reader.read_u32()?,
+        from_index: reader.read_u32()?,
+        from_left_index: reader.read_u32()?,
+        from_right_index: reader.read_u32()?,
+        dkim_header_index: reader.read_u32()?,
+        selector_index: reader.read_u32()?,
+        selector_right_index: reader.read_u32()?,
+        sdid_index: reader.read_u32()?,
+        sdid_right_index: reader.read_u32()?,
+        email_header: reader.read_bytes()?,
+        dkim_sig: reader.read_bytes()?,
+        subject_segments: vec![],
+        body: None,
+    };
+    if version >= 1 {
+        for _ in 0..reader.read_u32()? {
+            unpacked
+                .subject_segments
+                .push((reader.read_u32()?, reader.read_u32()?));
+        }
+        if reader.read_u8()? == 1 {
+            unpacked.body = Some(BodyArgs {
+                body_hash_index: reader.read_u32()?,
+                body_length: reader.read_u32()?,
+                recovery_hash_index: reader.read_u32()?,
+                body: reader.read_bytes()?,
+            });
+        }
+    }
+    reader.finish()?;
+    Ok(unpacked)
+}
+
+#[test]
+fn test_args_versions() {
+    use crate::{mock::construct_email, mock::EmailOptions, parser::parse_email};
+
+    let signing_key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+    let email = construct_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "=?UTF-8?B?dGVzdA==?= subject".to_string(),
+        "test body".to_string(),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &EmailOptions::default(),
+    );
+    let params = parse_email(&email).unwrap();
+
+    let v0 = unpack_args(&pack_args(&params, None, 0).unwrap()).unwrap();
+    assert_eq!(v0.version, 0);
+    assert_eq!(v0.email_header, params.email_header);
+    assert_eq!(v0.dkim_sig, params.dkim_sig);
+    assert!(v0.subject_segments.is_empty());
+    assert!(pack_args(&params, Some(0), 0).is_err());
+
+    let v1 = unpack_args(&pack_args(&params, Some(5), 1).unwrap()).unwrap();
+    assert_eq!(v1.version, 1);
+    assert_eq!(v1.subject_segments, params.subject_segments);
+    assert_eq!(v1.body.unwrap().recovery_hash_index, 5);
+    assert_eq!(
+        UnpackedArgs {
+            version: 0,
+            subject_segments: vec![],
+            body: None,
+            ..unpack_args(&pack_args(&params, None, 1).unwrap()).unwrap()
+        },
+        v0
+    );
+
+    let mut trailing = pack_args(&params, None, 1).unwrap();
+    trailing.push(0);
+    assert!(unpack_args(&trailing).is_err());
+}
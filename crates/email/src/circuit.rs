@@ -0,0 +1,322 @@
+//! Witness export for zk-email style circuits.
+//!
+//! Circom circuits take the SHA-256 padded header and RSA values as limbs of
+//! `limb_bits`, Noir circuits take the raw header as a `BoundedVec` and hash
+//! it with `sha256_var`, so the two targets only share the index fields.
+
+use rsa::{traits::PublicKeyParts, BigUint, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{compress256, digest::generic_array::GenericArray};
+
+use crate::{
+    error::ParserError,
+    types::{DkimParams, PrivateInputs},
+    ParserResult,
+};
+
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitTarget {
+    Circom,
+    Noir,
+}
+
+pub fn parse_circuit_target(s: &str) -> Result<CircuitTarget, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "circom" => Ok(CircuitTarget::Circom),
+        "noir" => Ok(CircuitTarget::Noir),
+        _ => Err(format!("invalid circuit target: `{}`", s)),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CircuitOptions {
+    pub target: CircuitTarget,
+    /// The header is padded with zeros to this many bytes, a multiple of 64.
+    pub max_header_len: usize,
+    /// Bits per RSA limb, 121 for zk-email's circom circuits and 120 for Noir.
+    pub limb_bits: usize,
+    /// `None` uses as many limbs as the key size needs, e.g. 17 and 18 for
+    /// 2048-bit keys.
+    pub limb_count: Option<usize>,
+    /// Hash the header blocks before the first occurrence of this string
+    /// outside the circuit and pass the partial SHA-256 state instead.
+    pub sha_precompute_selector: Option<String>,
+    pub from_pepper: Vec<u8>,
+}
+
+impl CircuitOptions {
+    pub fn new(target: CircuitTarget, from_pepper: Vec<u8>) -> Self {
+        let limb_bits = match target {
+            CircuitTarget::Circom => 121,
+            CircuitTarget::Noir => 120,
+        };
+        CircuitOptions {
+            target,
+            max_header_len: 1024,
+            limb_bits,
+            limb_count: None,
+            sha_precompute_selector: None,
+            from_pepper,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircomInputs {
+    pub email_header: Vec<String>,
+    pub email_header_length: String,
+    #[serde(rename = "precomputedSHA")]
+    pub precomputed_sha: Vec<String>,
+    pub pubkey: Vec<String>,
+    pub signature: Vec<String>,
+    pub from_pepper: Vec<String>,
+    pub from_index: String,
+    pub from_left_index: String,
+    pub from_right_index: String,
+    pub subject_index: String,
+    pub subject_right_index: String,
+    pub dkim_header_index: String,
+    pub selector_index: String,
+    pub selector_right_index: String,
+    pub sdid_index: String,
+    pub sdid_right_index: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NoirBoundedVec {
+    pub storage: Vec<u8>,
+    pub len: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NoirPubkey {
+    pub modulus: Vec<String>,
+    pub redc: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NoirInputs {
+    pub header: NoirBoundedVec,
+    pub partial_hash: [u32; 8],
+    pub pubkey: NoirPubkey,
+    pub signature: Vec<String>,
+    pub from_pepper: Vec<u8>,
+    pub from_index: usize,
+    pub from_left_index: usize,
+    pub from_right_index: usize,
+    pub subject_index: usize,
+    pub subject_right_index: usize,
+    pub dkim_header_index: usize,
+    pub selector_index: usize,
+    pub selector_right_index: usize,
+    pub sdid_index: usize,
+    pub sdid_right_index: usize,
+}
+
+/// Appends the SHA-256 padding: `0x80`, zeros and the 64-bit bit length.
+pub fn sha256_pad(data: &[u8]) -> Vec<u8> {
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend(((data.len() as u64) * 8).to_be_bytes());
+    padded
+}
+
+/// The SHA-256 state after compressing `blocks`, whose length must be a
+/// multiple of 64.
+pub fn sha256_partial_state(blocks: &[u8]) -> [u32; 8] {
+    let mut state = SHA256_IV;
+    let blocks: Vec<_> = blocks
+        .chunks(64)
+        .map(GenericArray::clone_from_slice)
+        .collect();
+    compress256(&mut state, &blocks);
+    state
+}
+
+/// Splits a big-endian integer into `count` little-endian limbs of `bits`.
+pub fn to_limbs(bytes_be: &[u8], bits: usize, count: usize) -> ParserResult<Vec<u128>> {
+    if bits == 0 || bits > 128 {
+        return Err(ParserError::SpecificError(format!(
+            "invalid limb size: {}",
+            bits
+        )));
+    }
+    let mut limbs = vec![0u128; count];
+    for (i, byte) in bytes_be.iter().rev().enumerate() {
+        for j in 0..8 {
+            if (byte >> j) & 1 == 1 {
+                let bit = i * 8 + j;
+                match limbs.get_mut(bit / bits) {
+                    Some(limb) => *limb |= 1 << (bit % bits),
+                    None => {
+                        return Err(ParserError::SpecificError(format!(
+                            "{} limbs of {} bits can't hold a {}-byte integer",
+                            count,
+                            bits,
+                            bytes_be.len()
+                        )))
+                    }
+                }
+            }
+        }
+    }
+    Ok(limbs)
+}
+
+/// Cuts the header at the last 64-byte boundary before `selector` and returns
+/// the cut offset with the SHA-256 state of the bytes before it.
+fn precompute(header: &[u8], selector: Option<&str>) -> ParserResult<(usize, [u32; 8])> {
+    let selector = match selector {
+        Some(selector) if !selector.is_empty() => selector,
+        _ => return Ok((0, SHA256_IV)),
+    };
+    let index = header
+        .windows(selector.len())
+        .position(|w| w == selector.as_bytes())
+        .ok_or_else(|| {
+            ParserError::SpecificError(format!("selector `{}` not in header", selector))
+        })?;
+    let cut = index / 64 * 64;
+    Ok((cut, sha256_partial_state(&header[..cut])))
+}
+
+/// Shifts the index fields by the precomputed prefix.
+fn shift(index: usize, cut: usize) -> ParserResult<usize> {
+    index.checked_sub(cut).ok_or_else(|| {
+        ParserError::SpecificError(format!(
+            "index {} lies in the precomputed part of the header",
+            index
+        ))
+    })
+}
+
+pub fn generate_inputs(
+    params: DkimParams,
+    public_key: &RsaPublicKey,
+    options: &CircuitOptions,
+) -> ParserResult<String> {
+    if options.max_header_len % 64 != 0 {
+        return Err(ParserError::SpecificError(format!(
+            "max header length {} is not a multiple of 64",
+            options.max_header_len
+        )));
+    }
+    let dkim_sig = params.dkim_sig.clone();
+    let inputs = PrivateInputs::from_params(params, options.from_pepper.clone());
+    let modulus = public_key.n();
+
+    let header = match options.target {
+        CircuitTarget::Circom => sha256_pad(&inputs.email_header),
+        CircuitTarget::Noir => inputs.email_header.clone(),
+    };
+    let (cut, partial_hash) = precompute(&header, options.sha_precompute_selector.as_deref())?;
+    let mut header = header[cut..].to_vec();
+    let header_len = header.len();
+    if header_len > options.max_header_len {
+        return Err(ParserError::SpecificError(format!(
+            "header of {} bytes exceeds max length {}",
+            header_len, options.max_header_len
+        )));
+    }
+    header.resize(options.max_header_len, 0);
+
+    // Noir also passes the Barrett parameter, which has up to 5 more bits.
+    let limb_count = options.limb_count.unwrap_or_else(|| {
+        let bits = modulus.bits()
+            + match options.target {
+                CircuitTarget::Circom => 0,
+                CircuitTarget::Noir => 5,
+            };
+        (bits + options.limb_bits - 1) / options.limb_bits
+    });
+    let limbs = |bytes: &[u8]| to_limbs(bytes, options.limb_bits, limb_count);
+
+    let res = match options.target {
+        CircuitTarget::Circom => {
+            let decimal = |values: Vec<u128>| -> Vec<String> {
+                values.iter().map(|v| v.to_string()).collect()
+            };
+            serde_json::to_string_pretty(&CircomInputs {
+                email_header: header.iter().map(|b| b.to_string()).collect(),
+                email_header_length: header_len.to_string(),
+                precomputed_sha: partial_hash
+                    .iter()
+                    .flat_map(|word| word.to_be_bytes())
+                    .map(|b| b.to_string())
+                    .collect(),
+                pubkey: decimal(limbs(&modulus.to_bytes_be())?),
+                signature: decimal(limbs(&dkim_sig)?),
+                from_pepper: inputs.from_pepper.iter().map(|b| b.to_string()).collect(),
+                from_index: shift(inputs.from_index, cut)?.to_string(),
+                from_left_index: shift(inputs.from_left_index, cut)?.to_string(),
+                from_right_index: shift(inputs.from_right_index, cut)?.to_string(),
+                subject_index: shift(inputs.subject_index, cut)?.to_string(),
+                subject_right_index: shift(inputs.subject_right_index, cut)?.to_string(),
+                dkim_header_index: shift(inputs.dkim_header_index, cut)?.to_string(),
+                selector_index: shift(inputs.selector_index, cut)?.to_string(),
+                selector_right_index: shift(inputs.selector_right_index, cut)?.to_string(),
+                sdid_index: shift(inputs.sdid_index, cut)?.to_string(),
+                sdid_right_index: shift(inputs.sdid_right_index, cut)?.to_string(),
+            })
+        }
+        CircuitTarget::Noir => {
+            let hex = |values: Vec<u128>| -> Vec<String> {
+                values.iter().map(|v| format!("0x{:x}", v)).collect()
+            };
+            // Barrett reduction parameter of noir-bignum.
+            let redc = (BigUint::from(1u32) << (2 * modulus.bits() + 4)) / modulus;
+            serde_json::to_string_pretty(&NoirInputs {
+                header: NoirBoundedVec {
+                    storage: header,
+                    len: header_len,
+                },
+                partial_hash,
+                pubkey: NoirPubkey {
+                    modulus: hex(limbs(&modulus.to_bytes_be())?),
+                    redc: hex(limbs(&redc.to_bytes_be())?),
+                },
+                signature: hex(limbs(&dkim_sig)?),
+                from_pepper: inputs.from_pepper.clone(),
+                from_index: shift(inputs.from_index, cut)?,
+                from_left_index: shift(inputs.from_left_index, cut)?,
+                from_right_index: shift(inputs.from_right_index, cut)?,
+                subject_index: shift(inputs.subject_index, cut)?,
+                subject_right_index: shift(inputs.subject_right_index, cut)?,
+                dkim_header_index: shift(inputs.dkim_header_index, cut)?,
+                selector_index: shift(inputs.selector_index, cut)?,
+                selector_right_index: shift(inputs.selector_right_index, cut)?,
+                sdid_index: shift(inputs.sdid_index, cut)?,
+                sdid_right_index: shift(inputs.sdid_right_index, cut)?,
+            })
+        }
+    };
+
+    Ok(res.unwrap())
+}
+
+#[test]
+fn test_sha256_partial_state() {
+    use sha2::{Digest, Sha256};
+
+    let data = b"partial sha256 state test".repeat(10);
+    let padded = sha256_pad(&data);
+    let state = sha256_partial_state(&padded);
+    let digest: Vec<u8> = state.iter().flat_map(|word| word.to_be_bytes()).collect();
+    assert_eq!(digest, Sha256::digest(&data).to_vec());
+
+    let limbs = to_limbs(
+        &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03],
+        64,
+        2,
+    )
+    .unwrap();
+    assert_eq!(limbs, vec![3, 1]);
+}
@@ -0,0 +1,105 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{digest::Update, Digest, Sha256};
+use utils::{from_0x_hex, to_0x_hex};
+
+use crate::{error::ParserError, ParserResult};
+
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// Normalizes an email address before it is committed to.
+///
+/// The domain is always lowercased since it is case-insensitive, the local
+/// part is kept as is. With `fold_gmail`, Gmail addresses additionally drop
+/// dots and `+tag`s from the local part, lowercase it and use `gmail.com`.
+pub fn normalize_address(address: &str, fold_gmail: bool) -> ParserResult<String> {
+    let address = address.trim();
+    let (local, domain) = match address.rsplit_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => (local, domain),
+        _ => return Err(ParserError::InvalidAddress(address.to_string())),
+    };
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+
+    if fold_gmail && GMAIL_DOMAINS.contains(&domain.as_str()) {
+        let local = local.split('+').next().unwrap_or(local).replace('.', "");
+        if local.is_empty() {
+            return Err(ParserError::InvalidAddress(address.to_string()));
+        }
+        return Ok(format!("{}@gmail.com", local.to_ascii_lowercase()));
+    }
+
+    Ok(format!("{}@{}", local, domain))
+}
+
+/// `sha256(from ‖ pepper)`, the hash that hides a guardian's address.
+pub fn from_hash(from: &[u8], pepper: &[u8]) -> Vec<u8> {
+    Sha256::default()
+        .chain(from)
+        .chain(pepper)
+        .finalize()
+        .to_vec()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmailCommitment {
+    pub address: String,
+    pub normalized_address: String,
+    pub pepper: String,
+    pub commitment: String,
+}
+
+/// Computes the `from_hash` commitment to register a privacy-preserving
+/// email guardian, generating a random 32-byte pepper when none is given.
+pub fn generate_commitment(
+    address: String,
+    pepper: Option<String>,
+    fold_gmail: bool,
+) -> ParserResult<String> {
+    let normalized_address = normalize_address(&address, fold_gmail)?;
+    let pepper = match pepper {
+        Some(pepper) => {
+            from_0x_hex(&pepper).map_err(|e| ParserError::InvalidPepper(e.to_string()))?
+        }
+        None => {
+            let mut pepper = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut pepper);
+            pepper
+        }
+    };
+
+    Ok(serde_json::to_string_pretty(&EmailCommitment {
+        commitment: to_0x_hex(from_hash(normalized_address.as_bytes(), &pepper)),
+        address,
+        normalized_address,
+        pepper: to_0x_hex(pepper),
+    })
+    .unwrap())
+}
+
+#[test]
+fn test_normalize_address() {
+    let cases = [
+        ("Alice@Test.COM", false, "Alice@test.com"),
+        (" alice@test.com. ", false, "alice@test.com"),
+        (
+            "A.lice+recovery@GoogleMail.com",
+            false,
+            "A.lice+recovery@googlemail.com",
+        ),
+        ("A.lice+recovery@GoogleMail.com", true, "alice@gmail.com"),
+        ("a.lice+x@test.com", true, "a.lice+x@test.com"),
+    ];
+    for (address, fold_gmail, expected) in cases {
+        assert_eq!(normalize_address(address, fold_gmail).unwrap(), expected);
+    }
+    assert!(normalize_address("alice", false).is_err());
+    // Nothing is left of the local part after folding.
+    assert!(normalize_address("+tag@gmail.com", true).is_err());
+    assert!(normalize_address("..@gmail.com", true).is_err());
+    assert!(generate_commitment(
+        "alice@test.com".to_string(),
+        Some("0xzz".to_string()),
+        false
+    )
+    .is_err());
+}
@@ -0,0 +1,211 @@
+//! RFC 2047 encoded-words and folding for unstructured header values.
+
+use base64::Engine;
+
+use crate::{error::ParserError, ParserResult};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubjectEncoding {
+    /// Let lettre decide, it only encodes non-ASCII words.
+    Auto,
+    /// `=?UTF-8?B?...?=`
+    Base64,
+    /// `=?UTF-8?Q?...?=`
+    Quoted,
+}
+
+/// The longest encoded-word RFC 2047 allows.
+const MAX_ENCODED_WORD_LEN: usize = 75;
+
+fn encode_word(text: &str, encoding: SubjectEncoding) -> String {
+    match encoding {
+        SubjectEncoding::Quoted => {
+            let mut out = String::from("=?UTF-8?Q?");
+            for b in text.bytes() {
+                match b {
+                    b' ' => out.push('_'),
+                    b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'!' | b'*' | b'+' | b'-' | b'/' => {
+                        out.push(b as char)
+                    }
+                    _ => out += &format!("={:02X}", b),
+                }
+            }
+            out + "?="
+        }
+        _ => format!(
+            "=?UTF-8?B?{}?=",
+            base64::engine::general_purpose::STANDARD.encode(text)
+        ),
+    }
+}
+
+/// Encodes and folds a Subject value the way MUAs do for long or non-ASCII
+/// subjects. Returns `None` when lettre's own encoding should be used.
+///
+/// `fold_width` is the maximum line length, the first line includes the
+/// `Subject: ` prefix. Adjacent encoded-words are separated by a fold when
+/// folding, or by a single space otherwise.
+pub fn encode_subject(
+    subject: &str,
+    encoding: SubjectEncoding,
+    fold_width: Option<usize>,
+) -> Option<String> {
+    let encoding = match (encoding, fold_width) {
+        (SubjectEncoding::Auto, None) => return None,
+        (SubjectEncoding::Auto, Some(width)) if subject.is_ascii() => {
+            return Some(fold_text(subject, width))
+        }
+        (SubjectEncoding::Auto, Some(_)) => SubjectEncoding::Base64,
+        (encoding, _) => encoding,
+    };
+
+    let max_word_len = fold_width
+        .map(|width| width.saturating_sub(1))
+        .unwrap_or(MAX_ENCODED_WORD_LEN)
+        .clamp(16, MAX_ENCODED_WORD_LEN);
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in subject.chars() {
+        let mut next = chunk.clone();
+        next.push(c);
+        if !chunk.is_empty() && encode_word(&next, encoding).len() > max_word_len {
+            words.push(encode_word(&chunk, encoding));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() || words.is_empty() {
+        words.push(encode_word(&chunk, encoding));
+    }
+
+    let separator = if fold_width.is_some() { "\r\n " } else { " " };
+    Some(words.join(separator))
+}
+
+/// Folds plain text at spaces so no line exceeds `width` where possible.
+fn fold_text(text: &str, width: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut line_len = "Subject: ".len();
+    for (i, word) in text.split(' ').enumerate() {
+        if i > 0 {
+            if line_len + 1 + word.len() > width {
+                out += "\r\n";
+                line_len = 0;
+            }
+            out.push(' ');
+            line_len += 1;
+        }
+        out += word;
+        line_len += word.len();
+    }
+    out
+}
+
+/// Decodes a single `=?charset?encoding?text?=` token.
+fn decode_word(word: &[u8]) -> Option<ParserResult<String>> {
+    let word = std::str::from_utf8(word).ok()?;
+    let inner = word.strip_prefix("=?")?.strip_suffix("?=")?;
+    let mut parts = inner.splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let text = parts.next()?;
+    // RFC 2231 language suffix, e.g. `UTF-8*en`.
+    let charset = charset.split('*').next().unwrap_or(charset);
+
+    let bytes = match encoding {
+        "B" | "b" => match base64::engine::general_purpose::STANDARD.decode(text) {
+            Ok(bytes) => bytes,
+            Err(e) => return Some(Err(ParserError::SpecificError(e.to_string()))),
+        },
+        "Q" | "q" => {
+            let mut bytes = Vec::with_capacity(text.len());
+            let mut iter = text.bytes();
+            while let Some(b) = iter.next() {
+                match b {
+                    b'_' => bytes.push(b' '),
+                    b'=' => {
+                        let hex = [iter.next().unwrap_or(b'0'), iter.next().unwrap_or(b'0')];
+                        match hex::decode(hex) {
+                            Ok(v) => bytes.extend(v),
+                            Err(e) => return Some(Err(ParserError::SpecificError(e.to_string()))),
+                        }
+                    }
+                    _ => bytes.push(b),
+                }
+            }
+            bytes
+        }
+        _ => return None,
+    };
+
+    Some(match charset.to_ascii_lowercase().as_str() {
+        "utf-8" | "us-ascii" => String::from_utf8(bytes).map_err(ParserError::from),
+        "iso-8859-1" | "latin1" => Ok(bytes.into_iter().map(char::from).collect()),
+        _ => Err(ParserError::SpecificError(format!(
+            "unsupported charset: `{}`",
+            charset
+        ))),
+    })
+}
+
+/// Decodes the header value in `array[left..right]`, unfolding it and decoding
+/// any encoded-words.
+///
+/// Besides the decoded text it returns the `[left, right)` span of every
+/// segment: each encoded-word, and each run of plain text within one folded
+/// line. Whitespace between two encoded-words is dropped as RFC 2047 requires.
+pub fn decode_header_value(
+    array: &[u8],
+    left: usize,
+    right: usize,
+) -> ParserResult<(String, Vec<(usize, usize)>)> {
+    let mut decoded = String::new();
+    let mut segments: Vec<(usize, usize)> = Vec::new();
+    let mut pending_space = String::new();
+    let mut last_encoded = false;
+    let mut can_merge = false;
+
+    let mut i = left;
+    while i < right {
+        match array[i] {
+            b'\r' | b'\n' => {
+                can_merge = false;
+                i += 1;
+            }
+            b' ' | b'\t' => {
+                pending_space.push(array[i] as char);
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                while i < right && !matches!(array[i], b' ' | b'\t' | b'\r' | b'\n') {
+                    i += 1;
+                }
+                match decode_word(&array[start..i]) {
+                    Some(text) => {
+                        if !last_encoded {
+                            decoded += &pending_space;
+                        }
+                        decoded += &text?;
+                        segments.push((start, i));
+                        last_encoded = true;
+                        can_merge = false;
+                    }
+                    None => {
+                        decoded += &pending_space;
+                        decoded += &String::from_utf8_lossy(&array[start..i]);
+                        match segments.last_mut() {
+                            Some(segment) if can_merge => segment.1 = i,
+                            _ => segments.push((start, i)),
+                        }
+                        last_encoded = false;
+                        can_merge = true;
+                    }
+                }
+                pending_space.clear();
+            }
+        }
+    }
+
+    Ok((decoded.trim_start().to_string(), segments))
+}
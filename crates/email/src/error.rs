@@ -5,10 +5,44 @@ use std::string::FromUtf8Error;
 pub enum ParserError {
     #[error("pubkey not found")]
     PubkeyNotFound,
-    #[error("header format error")]
-    HeaderFormatError,
+    #[error("bare LF at offset {offset}, strict mode only takes CRLF")]
+    InvalidLineEnding { offset: usize },
+    #[error("header `{name}` not found, searched from offset {start}")]
+    MissingHeader { name: String, start: usize },
+    #[error("header `{name}` at offset {start} has no terminating CRLF")]
+    UnterminatedHeader { name: String, start: usize },
+    #[error("{count} `{name}` headers, expected one")]
+    DuplicateHeader { name: String, count: usize },
+    #[error("invalid From header at offset {offset}: {reason}")]
+    InvalidFrom { offset: usize, reason: String },
+    #[error("no DKIM-Signature header{}", .domain.as_ref().map(|d| format!(" with d={}", d)).unwrap_or_default())]
+    MissingDkimSignature { domain: Option<String> },
+    #[error("tag `{tag}=` not found, searched from offset {start}")]
+    MissingTag { tag: String, start: usize },
+    #[error("value `{value}` of tag `{tag}=` not found, searched from offset {start}")]
+    MissingTagValue {
+        tag: String,
+        value: String,
+        start: usize,
+    },
+    #[error("invalid tag `{tag}=` at offset {offset}: {reason}")]
+    InvalidTag {
+        tag: String,
+        offset: usize,
+        reason: String,
+    },
+    #[error("empty recovery hash")]
+    EmptyRecoveryHash,
+    #[error("recovery hash not in the signed body of {body_length} bytes")]
+    MissingBodyRecoveryHash { body_length: usize },
     #[error("error:`{0}`")]
     SpecificError(String),
+    #[error("invalid email address: `{0}`")]
+    InvalidAddress(String),
+    #[error("invalid pepper: `{0}`")]
+    InvalidPepper(String),
+    #[error("invalid args: `{0}`")]
+    InvalidArgs(String),
     #[error("dkim error: `{0}`")]
     DkimParsingError(String),
     #[error(transparent)]
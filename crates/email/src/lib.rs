@@ -1,22 +1,30 @@
-use std::io::Write;
-
+use args::{pack_args, ARGS_VERSION};
 use error::ParserError;
-use ethers::abi::{Token, Tokenizable};
-use lettre::message::{DkimSigningAlgorithm, DkimSigningKey};
-use mock::construct_email;
-use parser::parse_email;
+use line_endings::{LineEndingMap, LineEndingMode};
+use mock::{construct_email, construct_recovery_reply, construct_recovery_request, EmailOptions};
+use parser::{parse_email, parse_email_with_mode};
 use rand::thread_rng;
 use rsa::{
-    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    pkcs1::DecodeRsaPrivateKey,
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
     traits::PublicKeyParts,
     RsaPrivateKey, RsaPublicKey,
 };
 use serde::{Deserialize, Serialize};
-use utils::to_0x_hex;
+use tamper::{check_email, construct_tampered_email, TamperKind, Verdict};
+use types::{ArcSet, DkimParams};
+use utils::{keystore::KeyFile, to_0x_hex};
 
+pub mod args;
+pub mod circuit;
+pub mod commitment;
+pub mod encoded_word;
 pub mod error;
+pub mod line_endings;
 pub mod mock;
 pub mod parser;
+pub mod signer;
+pub mod tamper;
 pub mod types;
 
 pub type ParserResult<T> = Result<T, ParserError>;
@@ -26,94 +34,270 @@ pub struct EmailArgs {
     pub pk: String,
     pub from: String,
     pub subject: String,
+    pub raw_subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_recovery_hash_index: Option<usize>,
+    pub args_version: u8,
+    pub args: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arc_sets: Vec<ArcSet>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmailReplyArgs {
+    pub pk: String,
+    pub from: String,
+    pub subject: String,
+    pub raw_subject: String,
+    /// Offset of the recovery hash in the signed header, if it appears
+    /// unencoded in the Subject.
+    pub recovery_hash_index: Option<usize>,
+    /// The wallet's request the reply answers.
+    pub request: String,
+    pub args_version: u8,
     pub args: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct EmailTamperFixture {
+    pub kind: String,
+    pub pk: String,
+    pub expected_verdict: Verdict,
+    pub reason: String,
+    /// What the parser and DKIM checks of this crate make of the email.
+    pub verdict: Verdict,
+    pub email: String,
+    /// Present when the email parses.
+    pub args: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ParsedEmail {
+    pub line_endings: LineEndingMap,
+    pub params: DkimParams,
+}
+
+/// Parses a raw email such as an `.eml` export.
+pub fn parse_raw_email(raw: &[u8], mode: LineEndingMode) -> String {
+    let (params, line_endings) = parse_email_with_mode(raw, mode).unwrap();
+    serde_json::to_string_pretty(&ParsedEmail {
+        line_endings,
+        params,
+    })
+    .unwrap()
+}
+
+/// Signs a mock email and parses it back into its DKIM params.
+pub fn generate_params(
+    key_file: &KeyFile,
+    rsa_bits: usize,
+    from: String,
+    to: String,
+    subject: String,
+    body: String,
+    selector: String,
+    domain: String,
+    options: &EmailOptions,
+) -> (DkimParams, RsaPublicKey) {
+    let (signing_key, public_key) = genearate_sk(key_file, rsa_bits);
+    let email = construct_email(
+        from,
+        to,
+        subject,
+        body,
+        selector,
+        domain,
+        &signing_key,
+        options,
+    );
+    (parse_email(&email).unwrap(), public_key)
+}
+
 pub fn generate_args(
-    create: bool,
-    sk_path: String,
+    key_file: &KeyFile,
+    rsa_bits: usize,
     from: String,
     to: String,
     subject: String,
     body: String,
     selector: String,
     domain: String,
+    options: EmailOptions,
 ) -> String {
-    let (signing_key, public_key) = genearate_sk(create, sk_path);
-    let email = construct_email(
+    let (params, public_key) = generate_params(
+        key_file,
+        rsa_bits,
         from.clone(),
         to,
-        subject.clone(),
+        subject,
         body,
         selector,
         domain,
-        signing_key,
+        &options,
     );
-    let params = parse_email(&email).unwrap();
+    encode_args(params, &public_key, from, None, ARGS_VERSION).unwrap()
+}
+
+fn raw_subject(params: &DkimParams) -> String {
+    String::from_utf8_lossy(
+        &params.email_header[params.subject_index + b"subject:".len()..params.subject_right_index],
+    )
+    .to_string()
+}
 
-    let args = ethers::abi::encode_packed(&[
-        (0u8).to_be_bytes().into_token(),
-        (params.subject_index as u32).to_be_bytes().into_token(),
-        (params.subject_right_index as u32)
-            .to_be_bytes()
-            .into_token(),
-        (params.from_index as u32).to_be_bytes().into_token(),
-        (params.from_left_index as u32).to_be_bytes().into_token(),
-        (params.from_right_index as u32).to_be_bytes().into_token(),
-        (params.dkim_header_index as u32).to_be_bytes().into_token(),
-        (params.selector_index as u32).to_be_bytes().into_token(),
-        (params.selector_right_index as u32)
-            .to_be_bytes()
-            .into_token(),
-        (params.sdid_index as u32).to_be_bytes().into_token(),
-        (params.sdid_right_index as u32).to_be_bytes().into_token(),
-        (params.email_header.len() as u32)
-            .to_be_bytes()
-            .into_token(),
-        Token::Bytes(params.email_header),
-        (params.dkim_sig.len() as u32).to_be_bytes().into_token(),
-        Token::Bytes(params.dkim_sig),
-    ])
-    .unwrap();
-
-    return serde_json::to_string_pretty(&EmailArgs {
+/// Formats the args JSON in the layout of `args_version`. With
+/// `body_recovery_hash` the recovery hash is proven from the body and the
+/// body section is added to the args.
+pub fn encode_args(
+    params: DkimParams,
+    public_key: &RsaPublicKey,
+    from: String,
+    body_recovery_hash: Option<String>,
+    args_version: u8,
+) -> ParserResult<String> {
+    let body_recovery_hash_index = body_recovery_hash
+        .map(|hash| {
+            if hash.is_empty() {
+                return Err(ParserError::EmptyRecoveryHash);
+            }
+            params
+                .body
+                .windows(hash.len())
+                .position(|w| w == hash.as_bytes())
+                .ok_or(ParserError::MissingBodyRecoveryHash {
+                    body_length: params.body.len(),
+                })
+        })
+        .transpose()?;
+    let args = pack_args(&params, body_recovery_hash_index, args_version)?;
+
+    Ok(serde_json::to_string_pretty(&EmailArgs {
         pk: public_key.n().to_str_radix(16),
         from,
-        subject,
+        raw_subject: raw_subject(&params),
+        body_recovery_hash_index,
+        args_version,
         args: to_0x_hex(args),
+        arc_sets: params.arc_sets,
+        subject: params.subject,
     })
-    .unwrap();
-}
-
-pub fn genearate_sk(create: bool, sk_path: String) -> (DkimSigningKey, RsaPublicKey) {
-    if create {
-        let mut rng = thread_rng();
-        let rsa_key = rsa::RsaPrivateKey::new(&mut rng, 2048).unwrap();
-        let private_key = rsa_key.to_pkcs1_pem(rsa::pkcs8::LineEnding::LF).unwrap();
-        let keypair = DkimSigningKey::new(&private_key, DkimSigningAlgorithm::Rsa).unwrap();
-        let mut file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&sk_path)
-            .unwrap();
-        file.write(private_key.as_bytes()).unwrap();
-        file.flush().unwrap();
-        (keypair, rsa_key.to_public_key())
-    } else {
-        let sk_pem = std::fs::read_to_string(&sk_path).unwrap();
-        let rsa_key = RsaPrivateKey::from_pkcs1_pem(&sk_pem).unwrap();
-        (
-            DkimSigningKey::new(&sk_pem, DkimSigningAlgorithm::Rsa).unwrap(),
-            rsa_key.to_public_key(),
-        )
+    .unwrap())
+}
+
+/// Mocks the reply flow: the wallet sends a recovery request and the guardian
+/// `from` replies to it from its own domain. The args are for the reply.
+pub fn generate_reply_args(
+    key_file: &KeyFile,
+    rsa_bits: usize,
+    from: String,
+    to: String,
+    subject: String,
+    recovery_hash: String,
+    body: String,
+    selector: String,
+    domain: String,
+    options: EmailOptions,
+    args_version: u8,
+) -> ParserResult<String> {
+    if recovery_hash.is_empty() {
+        return Err(ParserError::EmptyRecoveryHash);
     }
+    let (signing_key, public_key) = genearate_sk(key_file, rsa_bits);
+    let (request, _) = construct_recovery_request(
+        to,
+        from.clone(),
+        subject,
+        recovery_hash.clone(),
+        "Please reply to this email to approve the recovery.".to_string(),
+    );
+    let reply = construct_recovery_reply(&request, body, selector, domain, &signing_key, &options);
+    let params = parse_email(&reply)?;
+    let recovery_hash_index = params.email_header[..params.subject_right_index]
+        .windows(recovery_hash.len())
+        .skip(params.subject_index)
+        .position(|w| w == recovery_hash.as_bytes())
+        .map(|index| index + params.subject_index);
+
+    Ok(serde_json::to_string_pretty(&EmailReplyArgs {
+        pk: public_key.n().to_str_radix(16),
+        from,
+        raw_subject: raw_subject(&params),
+        recovery_hash_index,
+        request: String::from_utf8_lossy(&request).to_string(),
+        args_version,
+        args: to_0x_hex(pack_args(&params, None, args_version)?),
+        subject: params.subject,
+    })
+    .unwrap())
+}
+
+/// Builds a tampered email of `kind` with its expected verdict.
+pub fn generate_tamper_fixture(
+    key_file: &KeyFile,
+    rsa_bits: usize,
+    from: String,
+    to: String,
+    subject: String,
+    body: String,
+    selector: String,
+    domain: String,
+    options: EmailOptions,
+    kind: TamperKind,
+    args_version: u8,
+) -> String {
+    let (signing_key, public_key) = genearate_sk(key_file, rsa_bits);
+    let tampered = construct_tampered_email(
+        kind,
+        from,
+        to,
+        subject,
+        body,
+        selector,
+        domain.clone(),
+        &signing_key,
+        &options,
+    );
+    let (verdict, params) = check_email(&tampered.email, &public_key, &domain);
+    let args = params.and_then(|params| pack_args(&params, None, args_version).ok());
+
+    serde_json::to_string_pretty(&EmailTamperFixture {
+        kind: kind.name().to_string(),
+        pk: public_key.n().to_str_radix(16),
+        expected_verdict: tampered.expected,
+        reason: tampered.reason.to_string(),
+        verdict,
+        email: String::from_utf8_lossy(&tampered.email).to_string(),
+        args: args.map(to_0x_hex),
+    })
+    .unwrap()
+}
+
+/// Creates a `rsa_bits` key in `key_file`, or loads the key there whatever
+/// its size.
+pub fn genearate_sk(key_file: &KeyFile, rsa_bits: usize) -> (RsaPrivateKey, RsaPublicKey) {
+    let rsa_key = if key_file.create {
+        let rsa_key = RsaPrivateKey::new(&mut thread_rng(), rsa_bits).unwrap();
+        let private_key = rsa_key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        key_file.store_pem(&private_key).unwrap();
+        rsa_key
+    } else {
+        // Keys created before the keystore are PKCS#1.
+        let sk_pem = key_file.load_pem().unwrap();
+        RsaPrivateKey::from_pkcs8_pem(&sk_pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&sk_pem))
+            .unwrap()
+    };
+    let public_key = rsa_key.to_public_key();
+    (rsa_key, public_key)
 }
 
 #[test]
 fn test_gen_email() {
-    let (signing_key, _public_key) = genearate_sk(true, "email.sk".to_string());
+    let key_file = KeyFile {
+        force: true,
+        ..KeyFile::new("email.sk".to_string(), true)
+    };
+    let (signing_key, _public_key) = genearate_sk(&key_file, 2048);
     let email = construct_email(
         "Alice <alice@test.com>".to_string(),
         "Bob <bob@test.com>".to_string(),
@@ -121,10 +305,289 @@ fn test_gen_email() {
         "test email".to_string(),
         "s2023".to_string(),
         "test.com".to_string(),
-        signing_key,
+        &signing_key,
+        &EmailOptions::default(),
     );
     let params = parse_email(&email).unwrap();
 
     println!("{}", serde_json::to_string_pretty(&params).unwrap());
     println!("{}", String::from_utf8_lossy(&email));
 }
+
+#[test]
+fn test_gen_email_simple_oversigned() {
+    use rsa::pkcs1v15::Pkcs1v15Sign;
+    use sha2::{Digest, Sha256};
+
+    let signing_key = RsaPrivateKey::new(&mut thread_rng(), 2048).unwrap();
+    let options = EmailOptions {
+        cc: Some("Carol <carol@test.com>".to_string()),
+        reply_to: Some("Alice <alice@test.com>".to_string()),
+        canonicalization: mock::parse_canonicalization("simple/simple").unwrap(),
+        signed_headers: [
+            "From",
+            "From",
+            "Subject",
+            "To",
+            "Date",
+            "Message-ID",
+            "Reply-To",
+            "Cc",
+        ]
+        .iter()
+        .map(|h| h.to_string())
+        .collect(),
+        ..Default::default()
+    };
+    let email = construct_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "0x12345678".to_string(),
+        "test email".to_string(),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &options,
+    );
+    let params = parse_email(&email).unwrap();
+
+    assert!(params.email_header[params.from_index..].starts_with(b"From:"));
+    assert_eq!(
+        &params.email_header[params.from_left_index..params.from_right_index + 1],
+        b"alice@test.com"
+    );
+    RsaPublicKey::from(&signing_key)
+        .verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(&params.email_header),
+            &params.dkim_sig,
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_gen_email_folded_encoded_subject() {
+    let signing_key = RsaPrivateKey::new(&mut thread_rng(), 2048).unwrap();
+    let subject = format!("恢复 0x{}", "ab".repeat(32));
+    for canonicalization in ["relaxed/relaxed", "simple/simple"] {
+        let options = EmailOptions {
+            canonicalization: mock::parse_canonicalization(canonicalization).unwrap(),
+            subject_encoding: mock::SubjectEncoding::Base64,
+            subject_fold_width: Some(40),
+            ..Default::default()
+        };
+        let email = construct_email(
+            "Alice <alice@test.com>".to_string(),
+            "Bob <bob@test.com>".to_string(),
+            subject.clone(),
+            "test email".to_string(),
+            "s2023".to_string(),
+            "test.com".to_string(),
+            &signing_key,
+            &options,
+        );
+        let params = parse_email(&email).unwrap();
+
+        assert_eq!(params.subject, subject);
+        assert!(params.subject_segments.len() > 1);
+        for (left, right) in &params.subject_segments {
+            assert!(params.email_header[*left..*right].starts_with(b"=?UTF-8?B?"));
+        }
+        let public = types::PrivateInputs::from_params(params, vec![])
+            .to_public()
+            .unwrap();
+        assert_eq!(public.subject, subject);
+    }
+}
+
+#[test]
+fn test_gen_recovery_reply() {
+    let signing_key = RsaPrivateKey::new(&mut thread_rng(), 2048).unwrap();
+    let recovery_hash = format!("0x{}", "cd".repeat(32));
+    let (request, message_id) = mock::construct_recovery_request(
+        "Wallet <recovery@wallet.com>".to_string(),
+        "Alice <alice@test.com>".to_string(),
+        "Recovery request".to_string(),
+        recovery_hash.clone(),
+        "Please reply.\r\nThanks".to_string(),
+    );
+    let reply = construct_recovery_reply(
+        &request,
+        "Approved".to_string(),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &EmailOptions::default(),
+    );
+    let params = parse_email(&reply).unwrap();
+
+    assert_eq!(params.from, "alice@test.com");
+    assert_eq!(
+        params.subject,
+        format!("Re: Recovery request {}", recovery_hash)
+    );
+    assert_eq!(
+        mock::header_value(&reply, "In-Reply-To").as_deref(),
+        Some(message_id.as_str())
+    );
+    assert!(String::from_utf8_lossy(&reply).contains("> Please reply."));
+}
+
+#[test]
+fn test_gen_email_body_length() {
+    let signing_key = RsaPrivateKey::new(&mut thread_rng(), 2048).unwrap();
+    let recovery_hash = format!("0x{}", "ef".repeat(32));
+    let options = EmailOptions {
+        body_length: Some(mock::BodyLength::Full),
+        ..Default::default()
+    };
+    let email = construct_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "test subject".to_string(),
+        format!("Recovery\r\n{}", recovery_hash),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &options,
+    );
+    // Content appended after signing is not covered by `l=`.
+    let mut appended = email.clone();
+    appended.extend(b"\r\nappended after signing\r\n");
+
+    for email in [email, appended] {
+        let params = parse_email(&email).unwrap();
+        assert_eq!(params.body_length, Some(params.body.len()));
+        assert!(params.email_header[params.body_hash_index - 3..].starts_with(b"bh="));
+        assert!(String::from_utf8_lossy(&params.body).contains(&recovery_hash));
+    }
+    // `l=` cuts the signed body off before the recovery hash.
+    let options = EmailOptions {
+        body_length: Some(mock::BodyLength::Bytes("Recovery".len())),
+        ..Default::default()
+    };
+    let email = construct_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "test subject".to_string(),
+        format!("Recovery\r\n{}", recovery_hash),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &options,
+    );
+    let params = parse_email(&email).unwrap();
+    let public_key = RsaPublicKey::from(&signing_key);
+    assert!(matches!(
+        encode_args(
+            params,
+            &public_key,
+            "alice@test.com".to_string(),
+            Some(recovery_hash),
+            ARGS_VERSION,
+        ),
+        Err(ParserError::MissingBodyRecoveryHash { body_length: 8 })
+    ));
+    let params = parse_email(&email).unwrap();
+    assert!(matches!(
+        encode_args(
+            params,
+            &public_key,
+            "alice@test.com".to_string(),
+            Some(String::new()),
+            ARGS_VERSION,
+        ),
+        Err(ParserError::EmptyRecoveryHash)
+    ));
+}
+
+#[test]
+fn test_gen_email_arc() {
+    use rsa::pkcs1v15::Pkcs1v15Sign;
+    use sha2::{Digest, Sha256};
+
+    let signing_key = RsaPrivateKey::new(&mut thread_rng(), 2048).unwrap();
+    let public_key = RsaPublicKey::from(&signing_key);
+    let options = EmailOptions {
+        arc: Some(mock::ArcOptions {
+            selector: "arc".to_string(),
+            domain: "lists.test.com".to_string(),
+            subject_prefix: Some("[guardians]".to_string()),
+            footer: Some("Sent via the guardians list".to_string()),
+        }),
+        ..Default::default()
+    };
+    let email = construct_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "0x12345678".to_string(),
+        "test email".to_string(),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &options,
+    );
+    let params = parse_email(&email).unwrap();
+    let verify = |data: &[u8], sig: &[u8]| {
+        public_key
+            .verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(data), sig)
+            .is_ok()
+    };
+
+    // The list broke the DKIM signature but its ARC set verifies.
+    assert!(!verify(&params.email_header, &params.dkim_sig));
+    assert_eq!(params.arc_sets.len(), 1);
+    let arc = &params.arc_sets[0];
+    assert_eq!(arc.chain_validation, "none");
+    assert_eq!(arc.sdid, "lists.test.com");
+    assert!(arc
+        .authentication_results
+        .contains("dkim=pass header.d=test.com"));
+    assert!(verify(&arc.message_header, &arc.message_sig));
+    assert!(verify(&arc.seal_header, &arc.seal_sig));
+    assert_eq!(
+        &arc.message_header[arc.from_left_index..arc.from_right_index + 1],
+        b"alice@test.com"
+    );
+    // A malformed ARC set is reported but doesn't fail the DKIM parse.
+    let malformed = String::from_utf8_lossy(&email).replacen("cv=none", "xv=none", 1);
+    let params = parse_email(malformed.as_bytes()).unwrap();
+    assert!(params.arc_sets.is_empty());
+    assert_eq!(params.arc_errors.len(), 1);
+    assert_eq!(params.arc_errors[0].instance, 1);
+}
+
+#[test]
+fn test_gen_email_key_sizes() {
+    use circuit::{generate_inputs, CircomInputs, CircuitOptions, CircuitTarget};
+
+    for bits in [1024, 3072] {
+        let signing_key = RsaPrivateKey::new(&mut thread_rng(), bits).unwrap();
+        let email = construct_email(
+            "Alice <alice@test.com>".to_string(),
+            "Bob <bob@test.com>".to_string(),
+            "0x12345678".to_string(),
+            "test email".to_string(),
+            "s2023".to_string(),
+            "test.com".to_string(),
+            &signing_key,
+            &EmailOptions::default(),
+        );
+        let params = parse_email(&email).unwrap();
+        assert_eq!(params.dkim_sig.len(), bits / 8);
+
+        let mut options = CircuitOptions::new(CircuitTarget::Circom, vec![0; 32]);
+        options.max_header_len = 1000;
+        assert!(
+            generate_inputs(params.clone(), &RsaPublicKey::from(&signing_key), &options).is_err()
+        );
+        let inputs = generate_inputs(
+            params,
+            &RsaPublicKey::from(&signing_key),
+            &CircuitOptions::new(CircuitTarget::Circom, vec![0; 32]),
+        )
+        .unwrap();
+        let inputs: CircomInputs = serde_json::from_str(&inputs).unwrap();
+        assert_eq!(inputs.pubkey.len(), (bits + 120) / 121);
+    }
+}
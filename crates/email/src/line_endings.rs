@@ -0,0 +1,123 @@
+//! Line ending detection and normalization of raw emails.
+//!
+//! DKIM is computed over CRLF line endings, but `.eml` exports often use bare
+//! LF. The parser rewrites every bare LF to CRLF first and keeps the original
+//! offsets of the rewritten LFs to map offsets between the two.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ParserError, ParserResult};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    Crlf,
+    Lf,
+    Mixed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEndingMode {
+    /// Rewrite bare LFs to CRLF.
+    Normalize,
+    /// Refuse anything but CRLF.
+    Strict,
+}
+
+pub fn parse_line_ending_mode(s: &str) -> Result<LineEndingMode, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "normalize" => Ok(LineEndingMode::Normalize),
+        "strict" => Ok(LineEndingMode::Strict),
+        _ => Err(format!("invalid line ending mode: `{}`", s)),
+    }
+}
+
+/// The detected line endings and where CRs were inserted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineEndingMap {
+    pub detected: LineEndings,
+    /// Original offsets of the bare LFs, ascending.
+    pub bare_lf_offsets: Vec<usize>,
+}
+
+impl LineEndingMap {
+    /// Maps an offset of the original input into the canonical one.
+    pub fn to_canonical(&self, original: usize) -> usize {
+        original + self.bare_lf_offsets.partition_point(|&lf| lf <= original)
+    }
+
+    /// Maps an offset of the canonical input back, an inserted CR maps to
+    /// the LF after it.
+    pub fn to_original(&self, canonical: usize) -> usize {
+        // The i-th CR was inserted at canonical offset `lf + i`.
+        let inserted = self
+            .bare_lf_offsets
+            .iter()
+            .enumerate()
+            .take_while(|(i, &lf)| lf + i < canonical)
+            .count();
+        canonical - inserted
+    }
+}
+
+pub fn detect_line_endings(raw: &[u8]) -> LineEndings {
+    let lf = raw.iter().filter(|&&b| b == b'\n').count();
+    let crlf = raw.windows(2).filter(|w| w == b"\r\n").count();
+    match (crlf, lf) {
+        (crlf, lf) if crlf == lf => LineEndings::Crlf,
+        (0, _) => LineEndings::Lf,
+        _ => LineEndings::Mixed,
+    }
+}
+
+/// Returns `raw` with CRLF line endings and the map back to `raw`.
+pub fn normalize_line_endings(
+    raw: &[u8],
+    mode: LineEndingMode,
+) -> ParserResult<(Vec<u8>, LineEndingMap)> {
+    let detected = detect_line_endings(raw);
+    let mut normalized = Vec::with_capacity(raw.len());
+    let mut bare_lf_offsets = Vec::new();
+    for (i, &b) in raw.iter().enumerate() {
+        if b == b'\n' && (i == 0 || raw[i - 1] != b'\r') {
+            if mode == LineEndingMode::Strict {
+                return Err(ParserError::InvalidLineEnding { offset: i });
+            }
+            bare_lf_offsets.push(i);
+            normalized.push(b'\r');
+        }
+        normalized.push(b);
+    }
+
+    Ok((
+        normalized,
+        LineEndingMap {
+            detected,
+            bare_lf_offsets,
+        },
+    ))
+}
+
+#[test]
+fn test_normalize_line_endings() {
+    let raw = b"a\nbc\r\nd\n";
+    let (normalized, map) = normalize_line_endings(raw, LineEndingMode::Normalize).unwrap();
+    assert_eq!(normalized, b"a\r\nbc\r\nd\r\n");
+    assert_eq!(map.detected, LineEndings::Mixed);
+    assert_eq!(map.bare_lf_offsets, vec![1, 7]);
+
+    for (original, canonical) in [(0, 0), (1, 2), (2, 3), (6, 7), (7, 9)] {
+        assert_eq!(map.to_canonical(original), canonical);
+        assert_eq!(map.to_original(canonical), original);
+    }
+    // The inserted CRs map to their LF.
+    assert_eq!(map.to_original(1), 1);
+    assert_eq!(map.to_original(8), 7);
+
+    assert_eq!(detect_line_endings(b"a\nb\n"), LineEndings::Lf);
+    assert!(matches!(
+        normalize_line_endings(raw, LineEndingMode::Strict),
+        Err(ParserError::InvalidLineEnding { offset: 1 })
+    ));
+}
@@ -1,10 +1,101 @@
 use lettre::{
     message::{
-        header::{ContentType, HeaderName},
-        DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningKey,
+        header::{ContentType, HeaderName, HeaderValue},
+        Mailbox,
     },
     Message,
 };
+use rand::RngCore;
+use rsa::RsaPrivateKey;
+
+pub use crate::{
+    encoded_word::SubjectEncoding,
+    signer::{parse_canonicalization, BodyLength},
+};
+pub use lettre::message::{DkimCanonicalization, DkimCanonicalizationType};
+
+use crate::{
+    encoded_word::encode_subject,
+    signer::{
+        arc_sign_message, field_name, header_fields, set_header, sign_message, split_message,
+        DkimSignConfig,
+    },
+};
+
+/// Optional knobs of the mock email on top of the basic message fields.
+#[derive(Clone, Debug)]
+pub struct EmailOptions {
+    pub cc: Option<String>,
+    pub reply_to: Option<String>,
+    pub canonicalization: DkimCanonicalization,
+    pub signed_headers: Vec<String>,
+    pub subject_encoding: SubjectEncoding,
+    /// Fold the Subject so no line is longer than this.
+    pub subject_fold_width: Option<usize>,
+    pub in_reply_to: Option<String>,
+    pub references: Option<String>,
+    /// Adds an `l=` tag to the signature.
+    pub body_length: Option<BodyLength>,
+    /// Forwards the signed email through a mailing list that adds an ARC set.
+    pub arc: Option<ArcOptions>,
+}
+
+/// A forwarder such as a mailing list. It verifies the DKIM signature, may
+/// change the message in ways that break it and adds an ARC set signed with
+/// the same key as the DKIM signature.
+#[derive(Clone, Debug)]
+pub struct ArcOptions {
+    pub selector: String,
+    pub domain: String,
+    /// Prepended to the Subject, e.g. `[list]`.
+    pub subject_prefix: Option<String>,
+    /// Appended to the body.
+    pub footer: Option<String>,
+}
+
+impl Default for EmailOptions {
+    fn default() -> Self {
+        EmailOptions {
+            cc: None,
+            reply_to: None,
+            canonicalization: DkimCanonicalization {
+                header: DkimCanonicalizationType::Relaxed,
+                body: DkimCanonicalizationType::Relaxed,
+            },
+            signed_headers: vec![
+                "From".to_string(),
+                "Subject".to_string(),
+                "To".to_string(),
+                "Date".to_string(),
+            ],
+            subject_encoding: SubjectEncoding::Auto,
+            subject_fold_width: None,
+            in_reply_to: None,
+            references: None,
+            body_length: None,
+            arc: None,
+        }
+    }
+}
+
+pub fn parse_body_length(s: &str) -> Result<BodyLength, String> {
+    match s {
+        "full" => Ok(BodyLength::Full),
+        _ => s
+            .parse()
+            .map(BodyLength::Bytes)
+            .map_err(|_| format!("invalid body length: `{}`", s)),
+    }
+}
+
+pub fn parse_subject_encoding(s: &str) -> Result<SubjectEncoding, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "auto" => Ok(SubjectEncoding::Auto),
+        "b" | "base64" => Ok(SubjectEncoding::Base64),
+        "q" | "quoted" => Ok(SubjectEncoding::Quoted),
+        _ => Err(format!("invalid subject encoding: `{}`", s)),
+    }
+}
 
 pub fn construct_email(
     from: String,
@@ -13,33 +104,203 @@ pub fn construct_email(
     body: String,
     selector: String,
     domain: String,
-    signing_key: DkimSigningKey,
+    signing_key: &RsaPrivateKey,
+    options: &EmailOptions,
 ) -> Vec<u8> {
-    let mut message = Message::builder()
+    let message = format_email(from, to, subject, body, options);
+    let auth_results = format!("dkim=pass header.d={} header.s={}", domain, selector);
+    let signed = sign_email(&message, selector, domain, signing_key, options);
+    match &options.arc {
+        Some(arc) => forward_email(&signed, arc, &auth_results, signing_key, options),
+        None => signed,
+    }
+}
+
+/// Applies the forwarder's changes to `raw` and adds its ARC set, covering
+/// the headers in `options.signed_headers`.
+pub fn forward_email(
+    raw: &[u8],
+    arc: &ArcOptions,
+    auth_results: &str,
+    signing_key: &RsaPrivateKey,
+    options: &EmailOptions,
+) -> Vec<u8> {
+    let mut raw = String::from_utf8_lossy(raw).to_string();
+    if let Some(prefix) = &arc.subject_prefix {
+        let subject = header_value(raw.as_bytes(), "Subject").unwrap_or_default();
+        raw = set_header(&raw, "Subject", &format!("{} {}", prefix, subject));
+    }
+    if let Some(footer) = &arc.footer {
+        raw = format!("{}\r\n-- \r\n{}\r\n", raw, footer);
+    }
+
+    let config = DkimSignConfig::new(
+        arc.selector.clone(),
+        arc.domain.clone(),
+        options.signed_headers.clone(),
+        DkimCanonicalization {
+            header: DkimCanonicalizationType::Relaxed,
+            body: DkimCanonicalizationType::Relaxed,
+        },
+    );
+    arc_sign_message(raw.as_bytes(), &config, auth_results, signing_key)
+}
+
+/// Formats the unsigned message.
+pub fn format_email(
+    from: String,
+    to: String,
+    subject: String,
+    body: String,
+    options: &EmailOptions,
+) -> Vec<u8> {
+    let mut builder = Message::builder()
         .from(from.parse().unwrap())
         .to(to.parse().unwrap())
-        .subject(subject)
+        .message_id(None)
+        .header(ContentType::TEXT_PLAIN);
+    builder = match encode_subject(
+        &subject,
+        options.subject_encoding,
+        options.subject_fold_width,
+    ) {
+        Some(encoded) => builder.raw_header(HeaderValue::dangerous_new_pre_encoded(
+            HeaderName::new_from_ascii_str("Subject"),
+            subject,
+            encoded,
+        )),
+        None => builder.subject(subject),
+    };
+    if let Some(cc) = &options.cc {
+        builder = builder.cc(cc.parse().unwrap());
+    }
+    if let Some(reply_to) = &options.reply_to {
+        builder = builder.reply_to(reply_to.parse().unwrap());
+    }
+    if let Some(in_reply_to) = &options.in_reply_to {
+        builder = builder.in_reply_to(in_reply_to.clone());
+    }
+    if let Some(references) = &options.references {
+        builder = builder.references(references.clone());
+    }
+    builder.body(body).unwrap().formatted()
+}
+
+/// Signs a formatted message with the signature options of `options`.
+pub fn sign_email(
+    message: &[u8],
+    selector: String,
+    domain: String,
+    signing_key: &RsaPrivateKey,
+    options: &EmailOptions,
+) -> Vec<u8> {
+    let mut dkim_config = DkimSignConfig::new(
+        selector,
+        domain,
+        options.signed_headers.clone(),
+        options.canonicalization,
+    );
+    dkim_config.body_length = options.body_length;
+
+    sign_message(message, &dkim_config, signing_key)
+}
+
+/// Returns the unfolded value of the first `name` header of a raw message.
+pub fn header_value(raw: &[u8], name: &str) -> Option<String> {
+    let raw = String::from_utf8_lossy(raw);
+    let (header, _) = split_message(&raw);
+    header_fields(header)
+        .into_iter()
+        .find(|field| field_name(field).eq_ignore_ascii_case(name))
+        .and_then(|field| field.split_once(':'))
+        .map(|(_, value)| value.replace("\r\n", "").trim().to_string())
+}
+
+/// Constructs the wallet's outbound recovery request, whose Subject carries
+/// the recovery hash. Returns the unsigned message and its Message-ID.
+pub fn construct_recovery_request(
+    wallet: String,
+    guardian: String,
+    subject: String,
+    recovery_hash: String,
+    body: String,
+) -> (Vec<u8>, String) {
+    let wallet: Mailbox = wallet.parse().unwrap();
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let message_id = format!("<{}@{}>", hex::encode(id), wallet.email.domain());
+
+    let message = Message::builder()
+        .from(wallet)
+        .to(guardian.parse().unwrap())
+        .subject(format!("{} {}", subject, recovery_hash))
+        .message_id(Some(message_id.clone()))
         .header(ContentType::TEXT_PLAIN)
         .body(body)
         .unwrap();
 
-    let dkim_config = DkimConfig::new(
-        selector,
-        domain,
-        signing_key,
-        vec![
-            HeaderName::new_from_ascii_str("From"),
-            HeaderName::new_from_ascii_str("Subject"),
-            HeaderName::new_from_ascii_str("To"),
-            HeaderName::new_from_ascii_str("Date"),
-        ],
-        DkimCanonicalization {
-            header: DkimCanonicalizationType::Relaxed,
-            body: DkimCanonicalizationType::Relaxed,
-        },
+    (message.formatted(), message_id)
+}
+
+/// Constructs the guardian's DKIM-signed reply to `request`, threading it
+/// with In-Reply-To/References and quoting the request body below `body`.
+pub fn construct_recovery_reply(
+    request: &[u8],
+    body: String,
+    selector: String,
+    domain: String,
+    signing_key: &RsaPrivateKey,
+    options: &EmailOptions,
+) -> Vec<u8> {
+    let header = |name: &str| header_value(request, name).unwrap_or_default();
+    let message_id = header("Message-ID");
+    let references = match header_value(request, "References") {
+        Some(references) => format!("{} {}", references, message_id),
+        None => message_id.clone(),
+    };
+    let subject = header("Subject");
+    let subject = if subject.to_ascii_lowercase().starts_with("re:") {
+        subject
+    } else {
+        format!("Re: {}", subject)
+    };
+
+    let raw = String::from_utf8_lossy(request);
+    let (_, request_body) = split_message(&raw);
+    let quoted: String = request_body
+        .trim_end_matches("\r\n")
+        .split("\r\n")
+        .map(|line| format!("> {}\r\n", line))
+        .collect();
+    let body = format!(
+        "{}\r\n\r\nOn {}, {} wrote:\r\n{}",
+        body,
+        header("Date"),
+        header("From"),
+        quoted
     );
 
-    message.sign(&dkim_config);
+    let mut options = options.clone();
+    options.in_reply_to = Some(message_id);
+    options.references = Some(references);
+    for name in ["In-Reply-To", "References"] {
+        if !options
+            .signed_headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name))
+        {
+            options.signed_headers.push(name.to_string());
+        }
+    }
 
-    message.formatted()
+    construct_email(
+        header("To"),
+        header("From"),
+        subject,
+        body,
+        selector,
+        domain,
+        signing_key,
+        &options,
+    )
 }
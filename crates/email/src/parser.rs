@@ -1,6 +1,18 @@
-use email_rs::{dkim, Email, Header};
+use base64::Engine;
+use email_rs::{Email, Header};
 
-use crate::{error::ParserError, types::DkimParams, ParserResult};
+use crate::{
+    encoded_word::decode_header_value,
+    error::ParserError,
+    line_endings::{normalize_line_endings, LineEndingMap, LineEndingMode},
+    mock::DkimCanonicalizationType,
+    signer::{
+        canonicalize_body, canonicalize_header, field_name, find_arc_field, header_fields,
+        parse_canonicalization, select_headers, split_message, ARC_HEADERS,
+    },
+    types::{ArcSet, ArcSetError, DkimParams},
+    ParserResult,
+};
 
 fn index_of_sub_array(array: &[u8], sub_array: &[u8], start: usize) -> Option<usize> {
     if sub_array.is_empty() {
@@ -12,84 +24,282 @@ fn index_of_sub_array(array: &[u8], sub_array: &[u8], start: usize) -> Option<us
         .map(|v| v + start)
 }
 
-fn parse_header(
-    dkim_msg: &[u8],
-    dkim_header: &Header,
-    dkim_sig: Vec<u8>,
-    from: String,
-) -> ParserResult<DkimParams> {
-    let from_index = match index_of_sub_array(dkim_msg, b"from:", 0) {
-        Some(index) => index,
-        None => return Err(ParserError::HeaderFormatError),
-    };
+/// Finds the header field `name` (lowercase, including the colon) at the start
+/// of a line. Header names keep their original case under simple
+/// canonicalization, so the match ignores ASCII case.
+fn index_of_header(array: &[u8], name: &[u8], start: usize) -> Option<usize> {
+    (start..(array.len() + 1).saturating_sub(name.len())).find(|&index| {
+        (index == 0 || array[index - 1] == b'\n')
+            && array[index..index + name.len()].eq_ignore_ascii_case(name)
+    })
+}
 
-    let from_end_index = match index_of_sub_array(&dkim_msg[from_index..], b"\r\n", from_index) {
-        Some(index) => index,
-        None => return Err(ParserError::HeaderFormatError),
-    };
+/// Finds the CRLF that ends the header field starting at `start`, skipping
+/// folded continuation lines.
+fn index_of_header_end(array: &[u8], start: usize) -> Option<usize> {
+    let mut index = start;
+    loop {
+        index = index_of_sub_array(array, b"\r\n", index)?;
+        match array.get(index + 2) {
+            Some(b' ' | b'\t') => index += 2,
+            _ => return Some(index),
+        }
+    }
+}
 
-    let from_left_index =
-        match index_of_sub_array(&dkim_msg, format!("<{}>", from).as_bytes(), from_index) {
-            Some(index) => {
-                if index < from_end_index {
-                    index + 1
-                } else {
-                    match index_of_sub_array(dkim_msg, from.as_bytes(), 0) {
-                        Some(index) => index,
-                        None => return Err(ParserError::HeaderFormatError),
+/// Locates the addr-spec of the single mailbox in `array[left..right]`, a
+/// From header value, and returns its `[left, right)` span.
+///
+/// Quoted strings and comments are skipped so a display name like
+/// `"Bob <bob@evil.com>"` can't be mistaken for the address, an angle-addr
+/// wins over a bare address, and group syntax is unwrapped. Anything other
+/// than exactly one mailbox is an error.
+fn find_from_address(array: &[u8], left: usize, right: usize) -> ParserResult<(usize, usize)> {
+    let mut addresses = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let mut current_is_angle = false;
+
+    let mut i = left;
+    while i < right {
+        match array[i] {
+            b'"' => {
+                i += 1;
+                while i < right && array[i] != b'"' {
+                    if array[i] == b'\\' {
+                        i += 1;
                     }
+                    i += 1;
                 }
+                i += 1;
             }
-            None => match index_of_sub_array(dkim_msg, from.as_bytes(), 0) {
-                Some(index) => index,
-                None => return Err(ParserError::HeaderFormatError),
-            },
-        };
+            b'(' => {
+                let mut depth = 0;
+                while i < right {
+                    match array[i] {
+                        b'\\' => i += 1,
+                        b'(' => depth += 1,
+                        b')' => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            b'<' => {
+                let end = match index_of_sub_array(&array[..right], b">", i) {
+                    Some(index) => index,
+                    None => {
+                        return Err(ParserError::InvalidFrom {
+                            offset: i,
+                            reason: "unclosed angle-addr".to_string(),
+                        })
+                    }
+                };
+                let mut addr_left = i + 1;
+                let mut addr_right = end;
+                while addr_left < addr_right && array[addr_left].is_ascii_whitespace() {
+                    addr_left += 1;
+                }
+                while addr_right > addr_left && array[addr_right - 1].is_ascii_whitespace() {
+                    addr_right -= 1;
+                }
+                current = Some((addr_left, addr_right));
+                current_is_angle = true;
+                i = end + 1;
+            }
+            b',' | b';' => {
+                addresses.extend(current.take());
+                current_is_angle = false;
+                i += 1;
+            }
+            b':' => {
+                // The display name of a group, the mailboxes follow.
+                current = None;
+                current_is_angle = false;
+                i += 1;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => i += 1,
+            _ => {
+                let start = i;
+                while i < right
+                    && !matches!(
+                        array[i],
+                        b' ' | b'\t' | b'\r' | b'\n' | b'"' | b'(' | b'<' | b',' | b':' | b';'
+                    )
+                {
+                    i += 1;
+                }
+                if !current_is_angle && current.is_none() && array[start..i].contains(&b'@') {
+                    current = Some((start, i));
+                }
+            }
+        }
+    }
+    addresses.extend(current);
 
-    let from_right_index = from_left_index + from.len() - 1;
+    match addresses.as_slice() {
+        [address] => Ok(*address),
+        [] => Err(ParserError::InvalidFrom {
+            offset: left,
+            reason: "no mailbox".to_string(),
+        }),
+        _ => Err(ParserError::InvalidFrom {
+            offset: left,
+            reason: "multiple from addresses".to_string(),
+        }),
+    }
+}
 
-    let subject_index = match index_of_sub_array(dkim_msg, b"subject:", 0) {
-        Some(index) => index,
-        None => return Err(ParserError::HeaderFormatError),
-    };
+/// `index_of_header` that names the missing header in its error.
+fn find_header(array: &[u8], name: &str, start: usize) -> ParserResult<usize> {
+    index_of_header(array, format!("{}:", name).as_bytes(), start).ok_or_else(|| {
+        ParserError::MissingHeader {
+            name: name.to_string(),
+            start,
+        }
+    })
+}
 
-    let subject_right_index = match index_of_sub_array(&dkim_msg, b"\r\n", subject_index) {
-        Some(index) => index,
-        None => return Err(ParserError::HeaderFormatError),
-    };
+/// `index_of_header_end` for the header `name` starting at `start`.
+fn find_header_end(array: &[u8], name: &str, start: usize) -> ParserResult<usize> {
+    index_of_header_end(array, start).ok_or_else(|| ParserError::UnterminatedHeader {
+        name: name.to_string(),
+        start,
+    })
+}
 
-    let dkim_header_index = match index_of_sub_array(dkim_msg, b"dkim-signature:", 0) {
-        Some(index) => index,
-        None => return Err(ParserError::HeaderFormatError),
-    };
+/// Returns `from_index`, `from_left_index` and the inclusive `from_right_index`.
+fn parse_from(dkim_msg: &[u8]) -> ParserResult<(usize, usize, usize)> {
+    let from_index = find_header(dkim_msg, "from", 0)?;
+    let from_end_index = find_header_end(dkim_msg, "from", from_index)?;
 
-    let sdid_index = {
-        let d_index = match index_of_sub_array(&dkim_msg, b"d=", dkim_header_index) {
-            Some(index) => index,
-            None => return Err(ParserError::HeaderFormatError),
-        };
+    let (from_left_index, from_right_index) =
+        find_from_address(dkim_msg, from_index + b"from:".len(), from_end_index)?;
+
+    Ok((from_index, from_left_index, from_right_index - 1))
+}
 
-        match index_of_sub_array(&dkim_msg, dkim_header.sdid.as_bytes(), d_index) {
-            Some(index) => index,
-            None => return Err(ParserError::HeaderFormatError),
+/// Finds the value of the DKIM tag `tag` in the tag list `array[start..end]`
+/// and returns its `[left, right)` span without surrounding whitespace.
+fn find_dkim_tag(array: &[u8], tag: &[u8], start: usize, end: usize) -> Option<(usize, usize)> {
+    let is_space = |b: u8| matches!(b, b' ' | b'\t' | b'\r' | b'\n');
+    let mut index = start;
+    while index < end {
+        let spec_end = array[index..end]
+            .iter()
+            .position(|&b| b == b';')
+            .map_or(end, |pos| index + pos);
+        if let Some(pos) = array[index..spec_end].iter().position(|&b| b == b'=') {
+            let eq = index + pos;
+            let mut name_left = index;
+            while name_left < eq && is_space(array[name_left]) {
+                name_left += 1;
+            }
+            let mut name_right = eq;
+            while name_right > name_left && is_space(array[name_right - 1]) {
+                name_right -= 1;
+            }
+            if &array[name_left..name_right] == tag {
+                let mut left = eq + 1;
+                while left < spec_end && is_space(array[left]) {
+                    left += 1;
+                }
+                let mut right = spec_end;
+                while right > left && is_space(array[right - 1]) {
+                    right -= 1;
+                }
+                return Some((left, right));
+            }
         }
+        index = spec_end + 1;
+    }
+    None
+}
+
+fn parse_header(
+    dkim_msg: &[u8],
+    dkim_header: &Header,
+    dkim_sig: Vec<u8>,
+    body: &str,
+) -> ParserResult<DkimParams> {
+    let (from_index, from_left_index, from_right_index) = parse_from(dkim_msg)?;
+    let from = String::from_utf8(dkim_msg[from_left_index..from_right_index + 1].to_vec())?;
+
+    let subject_index = find_header(dkim_msg, "subject", 0)?;
+    let subject_right_index = find_header_end(dkim_msg, "subject", subject_index)?;
+
+    let (subject, subject_segments) = decode_header_value(
+        dkim_msg,
+        subject_index + b"subject:".len(),
+        subject_right_index,
+    )?;
+
+    let dkim_header_index = find_header(dkim_msg, "dkim-signature", 0)?;
+
+    // The DKIM-Signature is the last header of the message being signed.
+    let dkim_header_right_index =
+        index_of_header_end(dkim_msg, dkim_header_index).unwrap_or(dkim_msg.len());
+    let tags_index = dkim_header_index + b"dkim-signature:".len();
+    let tag = |name: &[u8]| find_dkim_tag(dkim_msg, name, tags_index, dkim_header_right_index);
+
+    // Locates the value of a tag the DKIM header was parsed with.
+    let find_tag_value = |name: &str, value: &str| -> ParserResult<usize> {
+        let (left, right) = tag(name.as_bytes()).ok_or_else(|| ParserError::MissingTag {
+            tag: name.to_string(),
+            start: tags_index,
+        })?;
+        if &dkim_msg[left..right] != value.as_bytes() {
+            return Err(ParserError::MissingTagValue {
+                tag: name.to_string(),
+                value: value.to_string(),
+                start: left,
+            });
+        }
+        Ok(left)
     };
 
+    let sdid_index = find_tag_value("d", &dkim_header.sdid)?;
     let sdid_right_index = sdid_index + dkim_header.sdid.len();
 
-    let selector_index = {
-        let s_index = match index_of_sub_array(&dkim_msg, b"s=", dkim_header_index) {
-            Some(index) => index,
-            None => return Err(ParserError::HeaderFormatError),
+    let selector_index = find_tag_value("s", &dkim_header.selector)?;
+    let selector_right_index = selector_index + dkim_header.selector.len();
+    let tag_value = |(left, right): (usize, usize)| String::from_utf8_lossy(&dkim_msg[left..right]);
+
+    let invalid_tag =
+        |tag: &str, (left, _): (usize, usize), reason: String| ParserError::InvalidTag {
+            tag: tag.to_string(),
+            offset: left,
+            reason,
         };
 
-        match index_of_sub_array(&dkim_msg, dkim_header.selector.as_bytes(), s_index) {
-            Some(index) => index,
-            None => return Err(ParserError::HeaderFormatError),
+    let body_hash_index = match tag(b"bh") {
+        Some((left, _)) => left,
+        None => {
+            return Err(ParserError::MissingTag {
+                tag: "bh".to_string(),
+                start: tags_index,
+            })
         }
     };
-
-    let selector_right_index = selector_index + dkim_header.selector.len();
+    let canonicalization = match tag(b"c") {
+        Some(span) => parse_canonicalization(&tag_value(span))
+            .map_err(|reason| invalid_tag("c", span, reason))?,
+        None => parse_canonicalization("simple/simple").unwrap(),
+    };
+    let body_length = match tag(b"l") {
+        Some(span) => Some(
+            tag_value(span)
+                .parse::<usize>()
+                .map_err(|e| invalid_tag("l", span, e.to_string()))?,
+        ),
+        None => None,
+    };
+    let mut body = canonicalize_body(body, canonicalization.body).into_bytes();
+    if let Some(length) = body_length {
+        body.truncate(length.min(body.len()));
+    }
 
     Ok(DkimParams {
         email_header: dkim_msg.to_vec(),
@@ -98,68 +308,316 @@ fn parse_header(
         from_right_index,
         subject_index,
         subject_right_index,
+        subject,
+        subject_segments,
         dkim_header_index,
         selector_index,
         selector_right_index,
         sdid_index,
         sdid_right_index,
+        body_hash_index,
+        body_length,
+        body,
         from,
         dkim_sig,
+        arc_sets: vec![],
+        arc_errors: vec![],
     })
 }
 
-pub fn parse_email_with_domain(email_raw_data: &[u8], domain: &str) -> ParserResult<DkimParams> {
-    let s = String::from_utf8_lossy(email_raw_data);
-    let email = Email::from_str(&s)?;
+/// The value span of the tag `tag` in a signature header field, offsets are
+/// into `field`.
+fn field_tag(field: &str, tag: &str) -> ParserResult<(usize, usize)> {
+    let start = field.find(':').map_or(0, |index| index + 1);
+    find_dkim_tag(field.as_bytes(), tag.as_bytes(), start, field.len()).ok_or_else(|| {
+        ParserError::MissingTag {
+            tag: tag.to_string(),
+            start,
+        }
+    })
+}
 
-    let binding = dkim::Header::new(Default::default(), Default::default());
-    let (dkim_msg, dkim_header) = match email
-        .get_dkim_message()
-        .into_iter()
-        .zip(email.dkim_headers.iter())
-        .find(|(_dkim_msg, dkim_header)| &dkim_header.sdid == domain)
-    {
-        Some((dkim_msg, dkim_header)) => (dkim_msg, dkim_header),
-        None => (Default::default(), &binding),
+/// Decodes the `b=` signature of a signature header field.
+fn field_signature(field: &str) -> ParserResult<Vec<u8>> {
+    let (left, right) = field_tag(field, "b")?;
+    let value: String = field[left..right]
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| ParserError::InvalidTag {
+            tag: "b".to_string(),
+            offset: left,
+            reason: e.to_string(),
+        })
+}
+
+/// The field with the value of its `b=` tag removed, as it is signed.
+fn without_signature(field: &str) -> ParserResult<String> {
+    let (left, right) = field_tag(field, "b")?;
+    Ok(format!("{}{}", &field[..left], &field[right..]))
+}
+
+/// Parses the ARC sets of a header block and rebuilds the data each
+/// ARC-Message-Signature and ARC-Seal signs. A set that can't be parsed is
+/// returned as an error and doesn't stop the others.
+pub fn parse_arc_sets(header: &str) -> (Vec<ArcSet>, Vec<ArcSetError>) {
+    let fields = header_fields(header);
+    let count = fields
+        .iter()
+        .filter(|field| field_name(field).eq_ignore_ascii_case("ARC-Seal"))
+        .count();
+
+    let mut chain = Vec::new();
+    let mut sets = Vec::new();
+    let mut errors = Vec::new();
+    for instance in 1..=count {
+        match parse_arc_set(&fields, instance, &mut chain) {
+            Ok(set) => sets.push(set),
+            Err(e) => errors.push(ArcSetError {
+                instance,
+                error: e.to_string(),
+            }),
+        }
+    }
+    (sets, errors)
+}
+
+/// Parses the ARC set of `instance`, adding its fields to `chain`, the
+/// sets the ARC-Seal of the next instance covers.
+fn parse_arc_set(fields: &[&str], instance: usize, chain: &mut Vec<u8>) -> ParserResult<ArcSet> {
+    let relaxed = DkimCanonicalizationType::Relaxed;
+    let tag_value =
+        |field: &str, tag: &str| field_tag(field, tag).map(|(l, r)| field[l..r].to_string());
+    let arc_field = |name: &str| {
+        find_arc_field(fields, name, instance).ok_or_else(|| ParserError::MissingHeader {
+            name: format!("{} i={}", name, instance),
+            start: 0,
+        })
+    };
+    let aar = arc_field(ARC_HEADERS[0])?;
+    let ams = arc_field(ARC_HEADERS[1])?;
+    let seal = arc_field(ARC_HEADERS[2])?;
+    let mut seal_header = chain.clone();
+    for field in [aar, ams, seal] {
+        chain.extend(canonicalize_header(field, relaxed).into_bytes());
+    }
+
+    let canonicalization = match field_tag(ams, "c") {
+        Ok((left, right)) => {
+            parse_canonicalization(&ams[left..right]).map_err(|reason| ParserError::InvalidTag {
+                tag: "c".to_string(),
+                offset: left,
+                reason,
+            })?
+        }
+        Err(_) => parse_canonicalization("simple/simple").unwrap(),
     };
+    let names: Vec<String> = tag_value(ams, "h")?
+        .split(':')
+        .map(|name| name.trim().to_string())
+        .collect();
+    let mut message_header = Vec::new();
+    for field in select_headers(fields, &names) {
+        message_header.extend(canonicalize_header(field, canonicalization.header).into_bytes());
+    }
+    message_header.extend(
+        canonicalize_header(&without_signature(ams)?, canonicalization.header)
+            .trim_end_matches("\r\n")
+            .as_bytes(),
+    );
+    let (from_index, from_left_index, from_right_index) = parse_from(&message_header)?;
 
-    let dkim_msg = dkim_msg.as_bytes();
+    seal_header.extend(canonicalize_header(aar, relaxed).into_bytes());
+    seal_header.extend(canonicalize_header(ams, relaxed).into_bytes());
+    seal_header.extend(
+        canonicalize_header(&without_signature(seal)?, relaxed)
+            .trim_end_matches("\r\n")
+            .as_bytes(),
+    );
 
-    let from = email
-        .get_header_item("from")
-        .map_err(|e| ParserError::DkimParsingError(e.to_string()))?;
-    let dkim_sig = dkim_header.signature.clone();
+    let authentication_results = aar
+        .split_once(':')
+        .and_then(|(_, value)| value.split_once(';'))
+        .map_or("", |(_, results)| results)
+        .replace("\r\n", "")
+        .trim()
+        .to_string();
+    Ok(ArcSet {
+        instance,
+        chain_validation: tag_value(seal, "cv")?,
+        authentication_results,
+        sdid: tag_value(ams, "d")?,
+        selector: tag_value(ams, "s")?,
+        message_header,
+        message_sig: field_signature(ams)?,
+        from_index,
+        from_left_index,
+        from_right_index,
+        seal_header,
+        seal_sig: field_signature(seal)?,
+    })
+}
 
-    let from = Email::<'_>::extract_address_of_from(from)
-        .map_err(|e| ParserError::DkimParsingError(e.to_string()))?;
+/// RFC 5322 allows one From field. With two, DKIM may cover one while the
+/// recipient's client shows the other.
+fn check_single_from(header: &str) -> ParserResult<()> {
+    let count = header_fields(header)
+        .into_iter()
+        .filter(|field| field_name(field).eq_ignore_ascii_case("from"))
+        .count();
+    if count > 1 {
+        return Err(ParserError::DuplicateHeader {
+            name: "from".to_string(),
+            count,
+        });
+    }
+    Ok(())
+}
 
-    parse_header(dkim_msg, dkim_header, dkim_sig, from)
+pub fn parse_email_with_domain(email_raw_data: &[u8], domain: &str) -> ParserResult<DkimParams> {
+    let (raw, _) = normalize_line_endings(email_raw_data, LineEndingMode::Normalize)?;
+    parse_canonical(&raw, Some(domain))
 }
 
 pub fn parse_email(email_raw_data: &[u8]) -> ParserResult<DkimParams> {
+    parse_email_with_mode(email_raw_data, LineEndingMode::Normalize).map(|(params, _)| params)
+}
+
+/// Parses `email_raw_data` after bringing it to CRLF line endings per `mode`.
+/// The returned map relates offsets of the input to the canonical message.
+pub fn parse_email_with_mode(
+    email_raw_data: &[u8],
+    mode: LineEndingMode,
+) -> ParserResult<(DkimParams, LineEndingMap)> {
+    let (raw, map) = normalize_line_endings(email_raw_data, mode)?;
+    Ok((parse_canonical(&raw, None)?, map))
+}
+
+/// Parses a CRLF message, using the first DKIM-Signature or the first one
+/// signed by `domain`.
+fn parse_canonical(email_raw_data: &[u8], domain: Option<&str>) -> ParserResult<DkimParams> {
     let s = String::from_utf8_lossy(email_raw_data);
     let email = Email::from_str(&s)?;
 
-    let binding = dkim::Header::new(Default::default(), Default::default());
     let (dkim_msg, dkim_header) = match email
         .get_dkim_message()
         .into_iter()
         .zip(email.dkim_headers.iter())
-        .find(|(_dkim_msg, _dkim_header)| true)
+        .find(|(_dkim_msg, dkim_header)| domain.map_or(true, |domain| dkim_header.sdid == domain))
     {
         Some((dkim_msg, dkim_header)) => (dkim_msg, dkim_header),
-        None => (Default::default(), &binding),
+        None => {
+            return Err(ParserError::MissingDkimSignature {
+                domain: domain.map(str::to_string),
+            })
+        }
     };
 
     let dkim_msg = dkim_msg.as_bytes();
-
-    let from = email
-        .get_header_item("from")
-        .map_err(|e| ParserError::DkimParsingError(e.to_string()))?;
     let dkim_sig = dkim_header.signature.clone();
+    let (header, body) = split_message(&s);
+    check_single_from(header)?;
+
+    let mut params = parse_header(dkim_msg, dkim_header, dkim_sig, body)?;
+    (params.arc_sets, params.arc_errors) = parse_arc_sets(header);
+    Ok(params)
+}
+
+#[test]
+fn test_parse_from_corpus() {
+    let corpus: [(&str, Option<(usize, usize)>); 15] = [
+        ("from:Alice <alice@test.com>", Some((12, 25))),
+        (
+            "from:\"Bob <bob@evil.com>\" <alice@test.com>",
+            Some((27, 40)),
+        ),
+        ("from:\"alice@test.com\" <bob@test.com>", Some((23, 34))),
+        ("from:=?UTF-8?B?5oGi5aSN?= <alice@test.com>", Some((27, 40))),
+        ("from:alice@test.com (Alice <bob@evil.com>)", Some((5, 18))),
+        (
+            "from:(comment) Alice <alice@test.com> (trailing)",
+            Some((22, 35)),
+        ),
+        ("From: Alice <Alice@Test.COM>", Some((13, 26))),
+        (
+            "from:\"Quoted \\\"<bob@evil.com>\\\" name\" <alice@test.com>",
+            Some((39, 52)),
+        ),
+        ("from:Alice\r\n <alice@test.com>", Some((14, 27))),
+        ("from:Friends: alice@test.com;", Some((14, 27))),
+        ("x-from:bob@evil.com\r\nfrom:alice@test.com", Some((26, 39))),
+        ("from:bob@evil.com <alice@test.com>", Some((19, 32))),
+        ("from:Alice <alice@test.com>, Bob <bob@test.com>", None),
+        ("from:undisclosed-recipients:;", None),
+        ("subject:alice@test.com", None),
+    ];
+
+    for (header, expected) in corpus {
+        let header = format!("{}\r\nsubject:test\r\n", header);
+        let res = parse_from(header.as_bytes())
+            .ok()
+            .map(|(_, left, right)| (left, right));
+        assert_eq!(res, expected, "{}", header);
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    use crate::mock::{format_email, EmailOptions};
+
+    let unsigned = format_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "test".to_string(),
+        "test".to_string(),
+        &EmailOptions::default(),
+    );
+    assert!(matches!(
+        parse_email(&unsigned),
+        Err(ParserError::MissingDkimSignature { domain: None })
+    ));
+    assert!(matches!(
+        parse_from(b"subject:test\r\n"),
+        Err(ParserError::MissingHeader { name, start: 0 }) if name == "from"
+    ));
+    assert!(matches!(
+        parse_from(b"from:Alice <alice@test.com\r\n"),
+        Err(ParserError::InvalidFrom { offset: 11, .. })
+    ));
+}
 
-    let from = Email::<'_>::extract_address_of_from(from)
-        .map_err(|e| ParserError::DkimParsingError(e.to_string()))?;
+#[test]
+fn test_dkim_tags_in_other_values() {
+    use crate::mock::{construct_email, EmailOptions};
 
-    parse_header(dkim_msg, dkim_header, dkim_sig, from)
+    let signing_key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let email = construct_email(
+        "Alice <alice@test.com>".to_string(),
+        "Bob <bob@test.com>".to_string(),
+        "test".to_string(),
+        "test".to_string(),
+        "s2023".to_string(),
+        "test.com".to_string(),
+        &signing_key,
+        &EmailOptions::default(),
+    );
+    // Unknown tags whose names end in `d` and `s` and whose values start
+    // with the real domain and selector, before the real tags.
+    let email = String::from_utf8(email).unwrap().replacen(
+        "v=1; ",
+        "v=1; xd=test.com.evil; xs=s2023evil; ",
+        1,
+    );
+    let params = parse_email(email.as_bytes()).unwrap();
+    let header = &params.email_header;
+    assert_eq!(
+        &header[params.sdid_index - 3..params.sdid_right_index + 1],
+        b" d=test.com;"
+    );
+    assert_eq!(
+        &header[params.selector_index - 3..params.selector_right_index + 1],
+        b" s=s2023;"
+    );
 }
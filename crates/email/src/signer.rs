@@ -0,0 +1,342 @@
+//! A small RFC 6376 DKIM signer for the mock emails.
+//!
+//! lettre's signer only supports one instance per header name and a fixed
+//! canonicalization, so the mocks format the message with lettre and sign the
+//! raw bytes here instead.
+
+use std::time::SystemTime;
+
+use base64::Engine;
+use lettre::message::{DkimCanonicalization, DkimCanonicalizationType};
+use rsa::{pkcs1v15::Pkcs1v15Sign, RsaPrivateKey};
+use sha2::{Digest, Sha256};
+
+/// The `l=` body length tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyLength {
+    /// The full canonicalized body length.
+    Full,
+    /// Only sign a prefix, capped at the canonicalized body length.
+    Bytes(usize),
+}
+
+pub struct DkimSignConfig {
+    pub selector: String,
+    pub domain: String,
+    /// Header names listed in `h=`. A name listed more times than it occurs is
+    /// oversigned, the missing instances are signed as empty.
+    pub headers: Vec<String>,
+    pub canonicalization: DkimCanonicalization,
+    pub body_length: Option<BodyLength>,
+    pub timestamp: u64,
+}
+
+impl DkimSignConfig {
+    pub fn new(
+        selector: String,
+        domain: String,
+        headers: Vec<String>,
+        canonicalization: DkimCanonicalization,
+    ) -> Self {
+        DkimSignConfig {
+            selector,
+            domain,
+            headers,
+            canonicalization,
+            body_length: None,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+}
+
+/// Splits a raw message into its header block (including the CRLF of the
+/// last header field) and its body.
+pub fn split_message(raw: &str) -> (&str, &str) {
+    match raw.find("\r\n\r\n") {
+        Some(index) => (&raw[..index + 2], &raw[index + 4..]),
+        None => (raw, ""),
+    }
+}
+
+/// Splits a header block into unfolded-aware header fields, each keeping its
+/// trailing CRLF.
+pub fn header_fields(header: &str) -> Vec<&str> {
+    let bytes = header.as_bytes();
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut cursor = 0;
+    while let Some(pos) = header[cursor..].find("\r\n") {
+        let end = cursor + pos + 2;
+        cursor = end;
+        if end < bytes.len() && (bytes[end] == b' ' || bytes[end] == b'\t') {
+            continue;
+        }
+        fields.push(&header[start..end]);
+        start = end;
+    }
+    if start < header.len() {
+        fields.push(&header[start..]);
+    }
+    fields
+}
+
+pub fn field_name(field: &str) -> &str {
+    match field.find(':') {
+        Some(index) => field[..index].trim_end(),
+        None => field,
+    }
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut in_whitespace = false;
+    for c in s.chars() {
+        if c == ' ' || c == '\t' {
+            if !in_whitespace {
+                out.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            out.push(c);
+            in_whitespace = false;
+        }
+    }
+    out
+}
+
+/// Parses a `c=` style canonicalization such as `relaxed/simple`. A missing
+/// body part defaults to `simple` as in RFC 6376.
+pub fn parse_canonicalization(s: &str) -> Result<DkimCanonicalization, String> {
+    let parse_type = |t: &str| match t.trim().to_ascii_lowercase().as_str() {
+        "simple" => Ok(DkimCanonicalizationType::Simple),
+        "relaxed" => Ok(DkimCanonicalizationType::Relaxed),
+        _ => Err(format!("invalid canonicalization: `{}`", t)),
+    };
+    let (header, body) = s.split_once('/').unwrap_or((s, "simple"));
+    Ok(DkimCanonicalization {
+        header: parse_type(header)?,
+        body: parse_type(body)?,
+    })
+}
+
+pub fn canonicalize_header(field: &str, canonicalization: DkimCanonicalizationType) -> String {
+    match canonicalization {
+        DkimCanonicalizationType::Simple => field.to_string(),
+        DkimCanonicalizationType::Relaxed => {
+            let (name, value) = field.split_once(':').unwrap_or((field, ""));
+            let value = collapse_whitespace(&value.replace("\r\n", ""));
+            format!(
+                "{}:{}\r\n",
+                name.trim_end().to_lowercase(),
+                value.trim_matches(' ')
+            )
+        }
+    }
+}
+
+pub fn canonicalize_body(body: &str, canonicalization: DkimCanonicalizationType) -> String {
+    let mut out = match canonicalization {
+        DkimCanonicalizationType::Simple => {
+            let mut out = body.to_string();
+            if !out.ends_with("\r\n") {
+                out += "\r\n";
+            }
+            out
+        }
+        DkimCanonicalizationType::Relaxed => body
+            .split("\r\n")
+            .map(|line| collapse_whitespace(line).trim_end_matches(' ').to_string() + "\r\n")
+            .collect(),
+    };
+    while out.ends_with("\r\n\r\n") {
+        out.truncate(out.len() - 2);
+    }
+    if out == "\r\n" && matches!(canonicalization, DkimCanonicalizationType::Relaxed) {
+        out.clear();
+    }
+    out
+}
+
+/// Picks the header fields named in `names`, matching each name against the
+/// last not yet used instance as RFC 6376 section 5.4.2 requires.
+pub fn select_headers<'a>(fields: &[&'a str], names: &[String]) -> Vec<&'a str> {
+    let mut used = vec![false; fields.len()];
+    let mut selected = Vec::new();
+    for name in names {
+        if let Some(index) = (0..fields.len())
+            .rev()
+            .find(|&i| !used[i] && field_name(fields[i]).eq_ignore_ascii_case(name))
+        {
+            used[index] = true;
+            selected.push(fields[index]);
+        }
+    }
+    selected
+}
+
+/// Builds the signature header field `name` over `raw`, starting its tag list
+/// with `leading_tags`. The returned field has no trailing CRLF.
+fn signature_field(
+    name: &str,
+    leading_tags: &str,
+    raw: &str,
+    config: &DkimSignConfig,
+    signing_key: &RsaPrivateKey,
+) -> String {
+    let base64_engine = base64::engine::general_purpose::STANDARD;
+    let (header, body) = split_message(raw);
+
+    let mut body = canonicalize_body(body, config.canonicalization.body).into_bytes();
+    let length_tag = match config.body_length {
+        Some(length) => {
+            if let BodyLength::Bytes(length) = length {
+                body.truncate(length.min(body.len()));
+            }
+            format!("l={}; ", body.len())
+        }
+        None => String::new(),
+    };
+    let body_hash = Sha256::digest(body);
+    let field = format!(
+        "{}: {}a=rsa-sha256; c={}; d={}; s={}; t={}; {}h={}; bh={}; b=",
+        name,
+        leading_tags,
+        config.canonicalization,
+        config.domain,
+        config.selector,
+        config.timestamp,
+        length_tag,
+        config.headers.join(":"),
+        base64_engine.encode(body_hash),
+    );
+
+    let mut hasher = Sha256::new();
+    for field in select_headers(&header_fields(header), &config.headers) {
+        hasher.update(canonicalize_header(field, config.canonicalization.header));
+    }
+    hasher.update(
+        canonicalize_header(&field, config.canonicalization.header).trim_end_matches("\r\n"),
+    );
+    let signature = signing_key
+        .sign(Pkcs1v15Sign::new::<Sha256>(), &hasher.finalize())
+        .unwrap();
+
+    format!("{}{}", field, base64_engine.encode(signature))
+}
+
+/// Signs `raw` and returns it with a `DKIM-Signature` header prepended.
+pub fn sign_message(raw: &[u8], config: &DkimSignConfig, signing_key: &RsaPrivateKey) -> Vec<u8> {
+    let raw = String::from_utf8_lossy(raw);
+    let dkim_field = signature_field("DKIM-Signature", "v=1; ", &raw, config, signing_key);
+    format!("{}\r\n{}", dkim_field, raw).into_bytes()
+}
+
+/// The header fields of one ARC set in the order ARC-Seal signs them.
+pub const ARC_HEADERS: [&str; 3] = [
+    "ARC-Authentication-Results",
+    "ARC-Message-Signature",
+    "ARC-Seal",
+];
+
+/// The `i=` instance of an ARC header field.
+pub fn arc_instance(field: &str) -> Option<usize> {
+    let (_, value) = field.split_once(':')?;
+    value.split(';').find_map(|tag| match tag.split_once('=') {
+        Some((name, value)) if name.trim() == "i" => value.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+/// Finds the ARC header `name` of `instance` among `fields`.
+pub fn find_arc_field<'a>(fields: &[&'a str], name: &str, instance: usize) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|field| {
+            field_name(field).eq_ignore_ascii_case(name) && arc_instance(field) == Some(instance)
+        })
+        .copied()
+}
+
+/// Adds the next ARC set to `raw` as RFC 8617 section 5.1 describes, for a
+/// forwarder that evaluated the message to `auth_results`. ARC-Seal always
+/// uses relaxed header canonicalization.
+pub fn arc_sign_message(
+    raw: &[u8],
+    config: &DkimSignConfig,
+    auth_results: &str,
+    signing_key: &RsaPrivateKey,
+) -> Vec<u8> {
+    let raw = String::from_utf8_lossy(raw);
+    let (header, _) = split_message(&raw);
+    let fields = header_fields(header);
+    let instance = fields
+        .iter()
+        .filter(|field| field_name(field).eq_ignore_ascii_case("ARC-Seal"))
+        .count()
+        + 1;
+
+    let aar = format!(
+        "ARC-Authentication-Results: i={}; {}; {}\r\n",
+        instance, config.domain, auth_results
+    );
+    let ams = signature_field(
+        "ARC-Message-Signature",
+        &format!("i={}; ", instance),
+        &raw,
+        config,
+        signing_key,
+    ) + "\r\n";
+    let seal = format!(
+        "ARC-Seal: i={}; a=rsa-sha256; t={}; cv={}; d={}; s={}; b=",
+        instance,
+        config.timestamp,
+        if instance == 1 { "none" } else { "pass" },
+        config.domain,
+        config.selector,
+    );
+
+    let relaxed = DkimCanonicalizationType::Relaxed;
+    let mut hasher = Sha256::new();
+    for i in 1..instance {
+        for name in ARC_HEADERS {
+            if let Some(field) = find_arc_field(&fields, name, i) {
+                hasher.update(canonicalize_header(field, relaxed));
+            }
+        }
+    }
+    hasher.update(canonicalize_header(&aar, relaxed));
+    hasher.update(canonicalize_header(&ams, relaxed));
+    hasher.update(canonicalize_header(&seal, relaxed).trim_end_matches("\r\n"));
+    let signature = signing_key
+        .sign(Pkcs1v15Sign::new::<Sha256>(), &hasher.finalize())
+        .unwrap();
+
+    format!(
+        "{}{}\r\n{}{}{}",
+        seal,
+        base64::engine::general_purpose::STANDARD.encode(signature),
+        ams,
+        aar,
+        raw
+    )
+    .into_bytes()
+}
+
+/// Replaces the value of the first `name` field, keeping its name as is.
+pub fn set_header(raw: &str, name: &str, value: &str) -> String {
+    let (header, body) = split_message(raw);
+    let fields: Vec<String> = header_fields(header)
+        .into_iter()
+        .map(|field| {
+            if field_name(field).eq_ignore_ascii_case(name) {
+                format!("{}: {}\r\n", field_name(field), value)
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    format!("{}\r\n{}", fields.concat(), body)
+}
@@ -0,0 +1,265 @@
+//! Malformed but plausible emails for negative contract tests.
+//!
+//! Each kind builds on `format_email` and `sign_email` and changes the
+//! message before or after signing. `check_email` runs the parser and the
+//! DKIM checks the contract performs, so a fixture records the verdict it
+//! expects next to the one it got.
+
+use base64::Engine;
+use rsa::{pkcs1v15::Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    mock::{format_email, sign_email, DkimCanonicalizationType, EmailOptions},
+    parser::parse_email,
+    signer::{field_name, header_fields, set_header, split_message},
+    types::DkimParams,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TamperKind {
+    /// A second From header appended after signing.
+    SecondFrom,
+    /// The Subject changed after signing.
+    ModifiedSubject,
+    /// A fake `dkim-signature:` tag list inside the signed Subject.
+    SubjectDkimSignature,
+    /// A CRLF and a From header injected through the Subject before signing.
+    SubjectCrlfInjection,
+    /// Line endings converted to bare LF in transit.
+    BareLf,
+    /// Header names in swapped case after signing.
+    HeaderCase,
+}
+
+pub const TAMPER_KINDS: [TamperKind; 6] = [
+    TamperKind::SecondFrom,
+    TamperKind::ModifiedSubject,
+    TamperKind::SubjectDkimSignature,
+    TamperKind::SubjectCrlfInjection,
+    TamperKind::BareLf,
+    TamperKind::HeaderCase,
+];
+
+impl TamperKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TamperKind::SecondFrom => "second-from",
+            TamperKind::ModifiedSubject => "modified-subject",
+            TamperKind::SubjectDkimSignature => "subject-dkim-signature",
+            TamperKind::SubjectCrlfInjection => "subject-crlf-injection",
+            TamperKind::BareLf => "bare-lf",
+            TamperKind::HeaderCase => "header-case",
+        }
+    }
+}
+
+pub fn parse_tamper_kind(s: &str) -> Result<TamperKind, String> {
+    TAMPER_KINDS
+        .into_iter()
+        .find(|kind| kind.name() == s.to_ascii_lowercase())
+        .ok_or_else(|| format!("invalid tamper kind: `{}`", s))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// Parsed, signed by `domain` and the signature and body hash verify.
+    Valid,
+    /// Parsed, but the signature or the body hash doesn't verify.
+    InvalidSignature,
+    /// The parser refuses the message or attributes it to another signer.
+    Rejected,
+}
+
+pub struct TamperedEmail {
+    pub email: Vec<u8>,
+    pub expected: Verdict,
+    pub reason: &'static str,
+}
+
+const MALLORY: &str = "Mallory <mallory@evil.com>";
+
+fn append_header(raw: &str, field: &str) -> String {
+    let (header, body) = split_message(raw);
+    format!("{}{}\r\n\r\n{}", header, field, body)
+}
+
+fn swap_case(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_uppercase() {
+                c.to_ascii_lowercase()
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect()
+}
+
+pub fn construct_tampered_email(
+    kind: TamperKind,
+    from: String,
+    to: String,
+    subject: String,
+    body: String,
+    selector: String,
+    domain: String,
+    signing_key: &RsaPrivateKey,
+    options: &EmailOptions,
+) -> TamperedEmail {
+    let sign = |message: &str| {
+        let signed = sign_email(
+            message.as_bytes(),
+            selector.clone(),
+            domain.clone(),
+            signing_key,
+            options,
+        );
+        String::from_utf8_lossy(&signed).to_string()
+    };
+    let message = String::from_utf8_lossy(&format_email(from, to, subject.clone(), body, options))
+        .to_string();
+
+    let (email, expected, reason) = match kind {
+        TamperKind::SecondFrom => (
+            append_header(&sign(&message), &format!("From: {}", MALLORY)),
+            Verdict::Rejected,
+            "a message must have exactly one From header",
+        ),
+        TamperKind::ModifiedSubject => (
+            set_header(
+                &sign(&message),
+                "Subject",
+                &format!("{} (modified)", subject),
+            ),
+            Verdict::InvalidSignature,
+            "the Subject is covered by the signature",
+        ),
+        TamperKind::SubjectDkimSignature => {
+            let fake = format!(
+                "{} dkim-signature:v=1; a=rsa-sha256; d=evil.com; s=evil; bh=; b=",
+                subject
+            );
+            (
+                sign(&set_header(&message, "Subject", &fake)),
+                Verdict::Valid,
+                "the fake tag list is Subject text, the signer is the real DKIM-Signature",
+            )
+        }
+        TamperKind::SubjectCrlfInjection => {
+            let injected = format!("{}\r\nFrom: {}", subject, MALLORY);
+            (
+                sign(&set_header(&message, "Subject", &injected)),
+                Verdict::Rejected,
+                "the injected line is a second From header",
+            )
+        }
+        TamperKind::BareLf => (
+            sign(&message).replace("\r\n", "\n"),
+            Verdict::Valid,
+            "verifiers restore CRLF line endings before checking the signature",
+        ),
+        TamperKind::HeaderCase => {
+            let signed = sign(&message);
+            let (header, body) = split_message(&signed);
+            let header: String = header_fields(header)
+                .into_iter()
+                .map(|field| {
+                    let name = field_name(field);
+                    format!("{}{}", swap_case(name), &field[name.len()..])
+                })
+                .collect();
+            match options.canonicalization.header {
+                DkimCanonicalizationType::Relaxed => (
+                    format!("{}\r\n{}", header, body),
+                    Verdict::Valid,
+                    "relaxed header canonicalization lowercases header names",
+                ),
+                DkimCanonicalizationType::Simple => (
+                    format!("{}\r\n{}", header, body),
+                    Verdict::InvalidSignature,
+                    "simple header canonicalization keeps header names as is",
+                ),
+            }
+        }
+    };
+
+    TamperedEmail {
+        email: email.into_bytes(),
+        expected,
+        reason,
+    }
+}
+
+/// Parses `email` and checks the signature and body hash against
+/// `public_key` and `domain` as the contract does.
+pub fn check_email(
+    email: &[u8],
+    public_key: &RsaPublicKey,
+    domain: &str,
+) -> (Verdict, Option<DkimParams>) {
+    let params = match parse_email(email) {
+        Ok(params) => params,
+        Err(_) => return (Verdict::Rejected, None),
+    };
+    if &params.email_header[params.sdid_index..params.sdid_right_index] != domain.as_bytes() {
+        return (Verdict::Rejected, Some(params));
+    }
+
+    let body_hash_right_index = params.email_header[params.body_hash_index..]
+        .iter()
+        .position(|&b| b == b';')
+        .map_or(params.email_header.len(), |pos| {
+            params.body_hash_index + pos
+        });
+    let body_hash = base64::engine::general_purpose::STANDARD
+        .decode(&params.email_header[params.body_hash_index..body_hash_right_index])
+        .unwrap_or_default();
+    let signature_valid = public_key
+        .verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(&params.email_header),
+            &params.dkim_sig,
+        )
+        .is_ok();
+    if !signature_valid || body_hash != Sha256::digest(&params.body).to_vec() {
+        return (Verdict::InvalidSignature, Some(params));
+    }
+    (Verdict::Valid, Some(params))
+}
+
+#[test]
+fn test_tamper_verdicts() {
+    let signing_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+    let public_key = RsaPublicKey::from(&signing_key);
+
+    for canonicalization in ["relaxed/relaxed", "simple/simple"] {
+        let options = EmailOptions {
+            canonicalization: crate::mock::parse_canonicalization(canonicalization).unwrap(),
+            ..Default::default()
+        };
+        for kind in TAMPER_KINDS {
+            let tampered = construct_tampered_email(
+                kind,
+                "Alice <alice@test.com>".to_string(),
+                "Bob <bob@test.com>".to_string(),
+                "0x12345678".to_string(),
+                "test body".to_string(),
+                "s2023".to_string(),
+                "test.com".to_string(),
+                &signing_key,
+                &options,
+            );
+            let (verdict, _) = check_email(&tampered.email, &public_key, "test.com");
+            assert_eq!(
+                verdict,
+                tampered.expected,
+                "{} {}",
+                kind.name(),
+                canonicalization
+            );
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use serde::{de, Deserialize, Serialize, Serializer};
-use sha2::{digest::Update, Digest, Sha256};
+use sha2::{Digest, Sha256};
 
-use crate::error::ParserError;
+use crate::{
+    commitment::{from_hash, normalize_address},
+    encoded_word::decode_header_value,
+    error::ParserError,
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -22,11 +26,76 @@ pub struct DkimParams {
     pub from_right_index: usize,
     pub subject_index: usize,
     pub subject_right_index: usize,
+    /// The Subject with folding removed and encoded-words decoded.
+    pub subject: String,
+    /// `[left, right)` of every encoded-word or folded line of the Subject.
+    pub subject_segments: Vec<(usize, usize)>,
     pub dkim_header_index: usize,
     pub selector_index: usize,
     pub selector_right_index: usize,
     pub sdid_index: usize,
     pub sdid_right_index: usize,
+    /// Offset of the `bh=` value in the DKIM-Signature header.
+    pub body_hash_index: usize,
+    /// The `l=` tag, when the signature only covers a prefix of the body.
+    pub body_length: Option<usize>,
+    /// The body as hashed into `bh=`: canonicalized and cut to `l=`.
+    #[serde(
+        deserialize_with = "deserialize_hex_string",
+        serialize_with = "serialize_hex_string"
+    )]
+    pub body: Vec<u8>,
+    /// The ARC sets added by forwarders, by ascending instance.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arc_sets: Vec<ArcSet>,
+    /// The ARC sets that couldn't be parsed. They don't fail the DKIM parse.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arc_errors: Vec<ArcSetError>,
+}
+
+/// One ARC set. `message_header` and `seal_header` are the data the
+/// ARC-Message-Signature and the ARC-Seal sign, as `email_header` is for the
+/// DKIM-Signature.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArcSet {
+    pub instance: usize,
+    /// The `cv=` tag of the ARC-Seal.
+    pub chain_validation: String,
+    pub authentication_results: String,
+    pub sdid: String,
+    pub selector: String,
+    #[serde(
+        deserialize_with = "deserialize_hex_string",
+        serialize_with = "serialize_hex_string"
+    )]
+    pub message_header: Vec<u8>,
+    #[serde(
+        deserialize_with = "deserialize_hex_string",
+        serialize_with = "serialize_hex_string"
+    )]
+    pub message_sig: Vec<u8>,
+    pub from_index: usize,
+    pub from_left_index: usize,
+    pub from_right_index: usize,
+    #[serde(
+        deserialize_with = "deserialize_hex_string",
+        serialize_with = "serialize_hex_string"
+    )]
+    pub seal_header: Vec<u8>,
+    #[serde(
+        deserialize_with = "deserialize_hex_string",
+        serialize_with = "serialize_hex_string"
+    )]
+    pub seal_sig: Vec<u8>,
+}
+
+/// An ARC set left out of `arc_sets`, with the reason.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArcSetError {
+    pub instance: usize,
+    pub error: String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -74,20 +143,26 @@ impl PrivateInputs {
 
     pub fn to_public(&self) -> Result<PublicInputs, ParserError> {
         let header_hash = Sha256::digest(&self.email_header).to_vec();
-        let from_hash = {
-            let hasher = Sha256::default();
-            let hasher = hasher
-                .chain(&self.email_header[self.from_left_index..self.from_right_index + 1])
-                .chain(&self.from_pepper);
-
-            hasher.finalize().to_vec()
-        };
+        let from = String::from_utf8(
+            self.email_header[self.from_left_index..self.from_right_index + 1].to_vec(),
+        )?;
+        let from_hash = from_hash(
+            normalize_address(&from, false)?.as_bytes(),
+            &self.from_pepper,
+        );
+        let subject_left_index = self.subject_index + b"subject:".len();
+        let (subject, _) = decode_header_value(
+            &self.email_header,
+            subject_left_index,
+            self.subject_right_index,
+        )?;
         Ok(PublicInputs {
             header_hash,
             from_hash,
-            subject: String::from_utf8(
-                self.email_header[self.subject_index + 8..self.subject_right_index].to_vec(),
+            raw_subject: String::from_utf8(
+                self.email_header[subject_left_index..self.subject_right_index].to_vec(),
             )?,
+            subject,
             selector: String::from_utf8(
                 self.email_header[self.selector_index..self.selector_right_index].to_vec(),
             )?,
@@ -111,6 +186,8 @@ pub struct PublicInputs {
         serialize_with = "serialize_hex_string"
     )]
     pub from_hash: Vec<u8>,
+    /// The Subject span as signed, possibly folded and encoded.
+    pub raw_subject: String,
     pub subject: String,
     pub selector: String,
     pub sdid: String,
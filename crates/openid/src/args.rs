@@ -0,0 +1,100 @@
+//! The OpenID guardian's contract args.
+//!
+//! Version 0, untagged:
+//!
+//! ```text
+//! issLeftIndex ‖ issRightIndex ‖ kidLeftIndex ‖ kidRightIndex ‖
+//! subLeftIndex ‖ subRightIndex ‖ audLeftIndex ‖ audRightIndex ‖
+//! nonceLeftIndex ‖ iatLeftIndex ‖ expLeftIndex ‖
+//! len ‖ header ‖ len ‖ payload ‖ len ‖ signature
+//! ```
+//!
+//! Version 1 prefixes the same fields with the tag `0x01 0x01`. All
+//! integers are big-endian `u32`. Version 0 args start with the high byte of
+//! `issLeftIndex`, which is zero, while tagged args start with the OpenID
+//! type `0x01`.
+
+use anyhow::bail;
+use utils::args::{args_tag, put_bytes, put_u32, ArgsReader, GuardianType};
+
+/// The layout `pack_args` emits by default.
+pub const ARGS_VERSION: u8 = 1;
+
+/// The decoded ID token and the claim offsets into it. Header and payload are
+/// the base64url-decoded JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdTokenArgs {
+    pub iss_left_index: usize,
+    pub iss_right_index: usize,
+    pub kid_left_index: usize,
+    pub kid_right_index: usize,
+    pub sub_left_index: usize,
+    pub sub_right_index: usize,
+    pub aud_left_index: usize,
+    pub aud_right_index: usize,
+    pub nonce_left_index: usize,
+    pub iat_left_index: usize,
+    pub exp_left_index: usize,
+    pub header: Vec<u8>,
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+pub fn pack_args(args: &IdTokenArgs, version: u8) -> anyhow::Result<Vec<u8>> {
+    let mut out = match version {
+        0 => vec![],
+        1 => args_tag(GuardianType::OpenID, version).to_vec(),
+        _ => bail!("unknown args version: {}", version),
+    };
+    for index in [
+        args.iss_left_index,
+        args.iss_right_index,
+        args.kid_left_index,
+        args.kid_right_index,
+        args.sub_left_index,
+        args.sub_right_index,
+        args.aud_left_index,
+        args.aud_right_index,
+        args.nonce_left_index,
+        args.iat_left_index,
+        args.exp_left_index,
+    ] {
+        put_u32(&mut out, index);
+    }
+    put_bytes(&mut out, &args.header);
+    put_bytes(&mut out, &args.payload);
+    put_bytes(&mut out, &args.signature);
+    Ok(out)
+}
+
+/// Decodes args of any version, returning the version with the fields.
+pub fn unpack_args(args: &[u8]) -> anyhow::Result<(u8, IdTokenArgs)> {
+    let mut reader = ArgsReader::new(args);
+    let version = if reader.peek_u8()? == 0 {
+        0
+    } else {
+        reader.read_tag(GuardianType::OpenID)?
+    };
+    if version > ARGS_VERSION {
+        bail!("unknown args version: {}", version);
+    }
+
+    let unpacked = IdTokenArgs {
+        iss_left_index: reader.read_u32()?,
+        iss_right_index: reader.read_u32()?,
+        kid_left_index: reader.read_u32()?,
+        kid_right_index: reader.read_u32()?,
+        sub_left_index: reader.read_u32()?,
+        sub_right_index: reader.read_u32()?,
+        aud_left_index: reader.read_u32()?,
+        aud_right_index: reader.read_u32()?,
+        nonce_left_index: reader.read_u32()?,
+        iat_left_index: reader.read_u32()?,
+        exp_left_index: reader.read_u32()?,
+        header: reader.read_bytes()?,
+        payload: reader.read_bytes()?,
+        signature: reader.read_bytes()?,
+    };
+    reader.finish()?;
+    Ok((version, unpacked))
+}
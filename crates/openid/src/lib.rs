@@ -1,11 +1,13 @@
-use std::{io::Write, time::Duration};
+use std::time::Duration;
 
+use args::{pack_args, IdTokenArgs};
 use base64::Engine;
-use ethers::abi::{Token, Tokenizable};
 use jwt_simple::prelude::*;
 use num_bigint_dig::BigUint;
 
-use utils::to_0x_hex;
+use utils::{keystore::KeyFile, to_0x_hex};
+
+pub mod args;
 
 #[derive(Serialize, Deserialize)]
 pub struct OpenIDArgs {
@@ -15,6 +17,7 @@ pub struct OpenIDArgs {
     pub sub: String,
     pub aud: String,
     pub nonce: String,
+    pub args_version: u8,
     pub args: String,
 }
 
@@ -34,35 +37,38 @@ pub fn generate_idtoken(
     return keypair.sign(claims).unwrap();
 }
 
-pub fn genearate_sk(create: bool, sk_path: String) -> RS256KeyPair {
-    if create {
-        let keypair = RS256KeyPair::generate(2048).unwrap();
-        let mut file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&sk_path)
-            .unwrap();
-        file.write(keypair.to_pem().unwrap().as_bytes()).unwrap();
-        file.flush().unwrap();
+/// jwt-simple only handles 2048, 3072 and 4096-bit RSA keys.
+pub fn parse_rsa_bits(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(bits @ (2048 | 3072 | 4096)) => Ok(bits),
+        _ => Err(format!("invalid RSA key size: `{}`", s)),
+    }
+}
+
+/// Creates a `rsa_bits` key in `key_file`, or loads the key there whatever
+/// its size.
+pub fn genearate_sk(key_file: &KeyFile, rsa_bits: usize) -> RS256KeyPair {
+    if key_file.create {
+        let keypair = RS256KeyPair::generate(rsa_bits).unwrap();
+        key_file.store_pem(&keypair.to_pem().unwrap()).unwrap();
         keypair
     } else {
-        let sk_pem = std::fs::read_to_string(&sk_path).unwrap();
+        let sk_pem = key_file.load_pem().unwrap();
         RS256KeyPair::from_pem(&sk_pem).unwrap()
     }
 }
 
 pub fn generate_args(
-    create: bool,
-    sk_path: String,
+    key_file: &KeyFile,
+    rsa_bits: usize,
     kid: String,
     iss: String,
     sub: String,
     aud: String,
     nonce: String,
+    args_version: u8,
 ) -> String {
-    let mut keypair = genearate_sk(create, sk_path);
+    let mut keypair = genearate_sk(key_file, rsa_bits);
 
     if kid.len() > 0 {
         keypair = keypair.with_key_id(&kid);
@@ -70,7 +76,7 @@ pub fn generate_args(
 
     let id_token = generate_idtoken(&keypair, &iss, &sub, &aud, &nonce);
 
-    return generate_contract_args(&keypair, id_token, kid, iss, sub, aud, nonce);
+    return generate_contract_args(&keypair, id_token, kid, iss, sub, aud, nonce, args_version);
 }
 
 pub fn generate_contract_args(
@@ -81,7 +87,29 @@ pub fn generate_contract_args(
     sub: String,
     aud: String,
     nonce: String,
+    args_version: u8,
 ) -> String {
+    let args = id_token_args(&id_token);
+    println!("header: {}", String::from_utf8_lossy(&args.header));
+    println!("payload: {}", String::from_utf8_lossy(&args.payload));
+    let data = pack_args(&args, args_version).unwrap();
+
+    let pk_comp = keypair.public_key().public_key().to_components();
+    return serde_json::to_string_pretty(&OpenIDArgs {
+        pk: BigUint::from_bytes_be(&pk_comp.n).to_str_radix(16),
+        kid,
+        iss,
+        sub,
+        aud,
+        nonce,
+        args_version,
+        args: to_0x_hex(data),
+    })
+    .unwrap();
+}
+
+/// Decodes an ID token and locates the claims the contract checks.
+pub fn id_token_args(id_token: &str) -> IdTokenArgs {
     let id_toeken_split: Vec<_> = id_token.split(".").collect();
     if id_toeken_split.len() != 3 {
         panic!("invalid id_token")
@@ -97,9 +125,6 @@ pub fn generate_contract_args(
         .decode(id_toeken_split[2].as_bytes())
         .unwrap();
 
-    println!("header: {}", String::from_utf8_lossy(&header));
-    println!("payload: {}", String::from_utf8_lossy(&payload));
-
     let field_end_value = r#"","#.as_bytes();
     let obj_end_value = r#""}"#.as_bytes();
 
@@ -132,38 +157,22 @@ pub fn generate_contract_args(
 
     let nonce_left_index = index_of_sub_array(&payload, r#""nonce":""#.as_bytes(), 0).unwrap() + 9;
 
-    let data = ethers::abi::encode_packed(&[
-        (iss_left_index as u32).to_be_bytes().into_token(),
-        (iss_right_index as u32).to_be_bytes().into_token(),
-        (kid_left_index as u32).to_be_bytes().into_token(),
-        (kid_right_index as u32).to_be_bytes().into_token(),
-        (sub_left_index as u32).to_be_bytes().into_token(),
-        (sub_right_index as u32).to_be_bytes().into_token(),
-        (aud_left_index as u32).to_be_bytes().into_token(),
-        (aud_right_index as u32).to_be_bytes().into_token(),
-        (nonce_left_index as u32).to_be_bytes().into_token(),
-        (iat_left_index as u32).to_be_bytes().into_token(),
-        (exp_left_index as u32).to_be_bytes().into_token(),
-        (header.len() as u32).to_be_bytes().into_token(),
-        Token::Bytes(header),
-        (payload.len() as u32).to_be_bytes().into_token(),
-        Token::Bytes(payload),
-        (signature.len() as u32).to_be_bytes().into_token(),
-        Token::Bytes(signature),
-    ])
-    .unwrap();
-
-    let pk_comp = keypair.public_key().public_key().to_components();
-    return serde_json::to_string_pretty(&OpenIDArgs {
-        pk: BigUint::from_bytes_be(&pk_comp.n).to_str_radix(16),
-        kid,
-        iss,
-        sub,
-        aud,
-        nonce,
-        args: to_0x_hex(data),
-    })
-    .unwrap();
+    IdTokenArgs {
+        iss_left_index,
+        iss_right_index,
+        kid_left_index,
+        kid_right_index,
+        sub_left_index,
+        sub_right_index,
+        aud_left_index,
+        aud_right_index,
+        nonce_left_index,
+        iat_left_index,
+        exp_left_index,
+        header,
+        payload,
+        signature,
+    }
 }
 
 fn index_of_sub_array(array: &[u8], sub_array: &[u8], start: usize) -> Option<usize> {
@@ -206,3 +215,18 @@ fn test_mock_idtoken() {
     println!("header: {}", String::from_utf8_lossy(&header_raw_bytes));
     println!("payload: {}", String::from_utf8_lossy(&payload_raw_bytes));
 }
+
+#[test]
+fn test_args_versions() {
+    let key = RS256KeyPair::generate(2048)
+        .unwrap()
+        .with_key_id("test_kid");
+    let id_token = generate_idtoken(&key, "test_iss", "test_sub", "test_aud", "test_nonce");
+    let args = id_token_args(&id_token);
+
+    for version in [0, args::ARGS_VERSION] {
+        let packed = pack_args(&args, version).unwrap();
+        assert_eq!(args::unpack_args(&packed).unwrap(), (version, args.clone()));
+    }
+    assert!(pack_args(&args, args::ARGS_VERSION + 1).is_err());
+}
@@ -0,0 +1,268 @@
+//! RS256 and EdDSA credentials. The passkey-rs authenticator only makes
+//! ES256 keys, so for the other algorithms a key generated here replaces its
+//! key in the attested credential data and signs the assertions again.
+
+use anyhow::{anyhow, bail};
+use coset::{cbor::Value, iana, Algorithm, CoseKey, KeyType, Label};
+use p256::ecdsa;
+use rsa::{
+    signature::{SignatureEncoding, Signer},
+    traits::{PrivateKeyParts, PublicKeyParts},
+    BigUint, RsaPrivateKey,
+};
+use sha2::Sha256;
+
+use crate::public_key::{bytes_param, p256_signing_key};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CredentialAlgorithm {
+    Es256,
+    Rs256,
+    EdDsa,
+}
+
+pub fn parse_credential_algorithm(s: &str) -> Result<CredentialAlgorithm, String> {
+    match s {
+        "es256" => Ok(CredentialAlgorithm::Es256),
+        "rs256" => Ok(CredentialAlgorithm::Rs256),
+        "eddsa" => Ok(CredentialAlgorithm::EdDsa),
+        _ => Err(format!("invalid algorithm: `{}`", s)),
+    }
+}
+
+/// The size of generated RS256 keys, as made by Windows Hello.
+const RSA_BITS: usize = 2048;
+
+fn int_param(label: i64, value: Vec<u8>) -> (Label, Value) {
+    (Label::Int(label), Value::Bytes(value))
+}
+
+impl CredentialAlgorithm {
+    pub fn cose(&self) -> iana::Algorithm {
+        match self {
+            CredentialAlgorithm::Es256 => iana::Algorithm::ES256,
+            CredentialAlgorithm::Rs256 => iana::Algorithm::RS256,
+            CredentialAlgorithm::EdDsa => iana::Algorithm::EdDSA,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CredentialAlgorithm::Es256 => "ES256",
+            CredentialAlgorithm::Rs256 => "RS256",
+            CredentialAlgorithm::EdDsa => "EdDSA",
+        }
+    }
+
+    /// The algorithm of a COSE_Key, from its `alg` or else its key type.
+    pub fn of_key(key: &CoseKey) -> anyhow::Result<Self> {
+        match (&key.alg, &key.kty) {
+            (Some(Algorithm::Assigned(iana::Algorithm::ES256)), _)
+            | (None, KeyType::Assigned(iana::KeyType::EC2)) => Ok(CredentialAlgorithm::Es256),
+            (Some(Algorithm::Assigned(iana::Algorithm::RS256)), _)
+            | (None, KeyType::Assigned(iana::KeyType::RSA)) => Ok(CredentialAlgorithm::Rs256),
+            (Some(Algorithm::Assigned(iana::Algorithm::EdDSA)), _)
+            | (None, KeyType::Assigned(iana::KeyType::OKP)) => Ok(CredentialAlgorithm::EdDsa),
+            (alg, kty) => bail!("unsupported COSE_Key: {:?} {:?}", kty, alg),
+        }
+    }
+
+    /// A new private COSE_Key. ES256 keys are made by the authenticator.
+    pub fn generate_key(&self) -> anyhow::Result<CoseKey> {
+        let (kty, params) = match self {
+            CredentialAlgorithm::Es256 => bail!("ES256 keys come from the authenticator"),
+            CredentialAlgorithm::Rs256 => {
+                let key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_BITS)?;
+                let [p, q] = key.primes() else {
+                    bail!("expected a two prime RSA key");
+                };
+                // dP, dQ and qInv are recomputed from these when loading.
+                let params = [
+                    (iana::RsaKeyParameter::N, key.n()),
+                    (iana::RsaKeyParameter::E, key.e()),
+                    (iana::RsaKeyParameter::D, key.d()),
+                    (iana::RsaKeyParameter::P, p),
+                    (iana::RsaKeyParameter::Q, q),
+                ]
+                .into_iter()
+                .map(|(label, value)| int_param(label as i64, value.to_bytes_be()))
+                .collect();
+                (iana::KeyType::RSA, params)
+            }
+            CredentialAlgorithm::EdDsa => {
+                let key = ed25519_dalek::SigningKey::from_bytes(&rand::random());
+                let params = vec![
+                    (
+                        Label::Int(iana::OkpKeyParameter::Crv as i64),
+                        Value::from(iana::EllipticCurve::Ed25519 as i64),
+                    ),
+                    int_param(
+                        iana::OkpKeyParameter::X as i64,
+                        key.verifying_key().to_bytes().to_vec(),
+                    ),
+                    int_param(iana::OkpKeyParameter::D as i64, key.to_bytes().to_vec()),
+                ];
+                (iana::KeyType::OKP, params)
+            }
+        };
+        Ok(CoseKey {
+            kty: KeyType::Assigned(kty),
+            alg: Some(Algorithm::Assigned(self.cose())),
+            params,
+            ..Default::default()
+        })
+    }
+}
+
+/// A signature in the encoding of its algorithm.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssertionSignature {
+    Es256 {
+        r: [u8; 32],
+        s: [u8; 32],
+    },
+    /// PKCS#1 v1.5, as long as the modulus.
+    Rs256(Vec<u8>),
+    /// `R ‖ S`.
+    EdDsa([u8; 64]),
+}
+
+impl From<ecdsa::Signature> for AssertionSignature {
+    fn from(signature: ecdsa::Signature) -> Self {
+        AssertionSignature::Es256 {
+            r: signature.r().to_bytes().into(),
+            s: signature.s().to_bytes().into(),
+        }
+    }
+}
+
+impl AssertionSignature {
+    pub fn algorithm(&self) -> CredentialAlgorithm {
+        match self {
+            AssertionSignature::Es256 { .. } => CredentialAlgorithm::Es256,
+            AssertionSignature::Rs256(_) => CredentialAlgorithm::Rs256,
+            AssertionSignature::EdDsa(_) => CredentialAlgorithm::EdDsa,
+        }
+    }
+
+    /// `r ‖ s` for ES256, otherwise the signature as is.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AssertionSignature::Es256 { r, s } => [&r[..], &s[..]].concat(),
+            AssertionSignature::Rs256(signature) => signature.clone(),
+            AssertionSignature::EdDsa(signature) => signature.to_vec(),
+        }
+    }
+
+    pub fn from_bytes(algorithm: CredentialAlgorithm, bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(match algorithm {
+            CredentialAlgorithm::Es256 => {
+                if bytes.len() != 64 {
+                    bail!("ES256 signatures are 64 bytes, got {}", bytes.len());
+                }
+                AssertionSignature::Es256 {
+                    r: bytes[..32].try_into()?,
+                    s: bytes[32..].try_into()?,
+                }
+            }
+            CredentialAlgorithm::Rs256 => AssertionSignature::Rs256(bytes.to_vec()),
+            CredentialAlgorithm::EdDsa => AssertionSignature::EdDsa(bytes.try_into()?),
+        })
+    }
+
+    /// The encoding of WebAuthn responses and attestation statements, which
+    /// is DER for ES256.
+    pub fn to_webauthn_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            AssertionSignature::Es256 { r, s } => {
+                let signature = ecdsa::Signature::from_scalars(*r, *s)?;
+                Ok(signature.to_der().as_bytes().to_vec())
+            }
+            _ => Ok(self.to_bytes()),
+        }
+    }
+}
+
+pub fn rsa_private_key(key: &CoseKey) -> anyhow::Result<RsaPrivateKey> {
+    let int = |label: iana::RsaKeyParameter, name: &str| {
+        bytes_param(key, label as i64, name).map(BigUint::from_bytes_be)
+    };
+    let mut private_key = RsaPrivateKey::from_components(
+        int(iana::RsaKeyParameter::N, "n")?,
+        int(iana::RsaKeyParameter::E, "e")?,
+        int(iana::RsaKeyParameter::D, "d")?,
+        vec![
+            int(iana::RsaKeyParameter::P, "p")?,
+            int(iana::RsaKeyParameter::Q, "q")?,
+        ],
+    )?;
+    private_key.precompute()?;
+    Ok(private_key)
+}
+
+pub fn ed25519_signing_key(key: &CoseKey) -> anyhow::Result<ed25519_dalek::SigningKey> {
+    let d = bytes_param(key, iana::OkpKeyParameter::D as i64, "d")?;
+    Ok(ed25519_dalek::SigningKey::from_bytes(
+        d.try_into()
+            .map_err(|_| anyhow!("Ed25519 private keys are 32 bytes"))?,
+    ))
+}
+
+/// Signs `message` with a private COSE_Key of any of the algorithms.
+pub fn sign(key: &CoseKey, message: &[u8]) -> anyhow::Result<AssertionSignature> {
+    Ok(match CredentialAlgorithm::of_key(key)? {
+        CredentialAlgorithm::Es256 => {
+            let signature: ecdsa::Signature = p256_signing_key(key)?.sign(message);
+            signature.into()
+        }
+        CredentialAlgorithm::Rs256 => {
+            let signing_key = rsa::pkcs1v15::SigningKey::<Sha256>::new(rsa_private_key(key)?);
+            AssertionSignature::Rs256(signing_key.sign(message).to_vec())
+        }
+        CredentialAlgorithm::EdDsa => {
+            let signature: ed25519_dalek::Signature = ed25519_signing_key(key)?.sign(message);
+            AssertionSignature::EdDsa(signature.to_bytes())
+        }
+    })
+}
+
+#[test]
+fn test_sign() {
+    use crate::public_key::CredentialPublicKey;
+    use rsa::signature::Verifier;
+
+    for algorithm in [CredentialAlgorithm::Rs256, CredentialAlgorithm::EdDsa] {
+        let key = algorithm.generate_key().unwrap();
+        assert_eq!(CredentialAlgorithm::of_key(&key).unwrap(), algorithm);
+        let signature = sign(&key, b"message").unwrap();
+        assert_eq!(signature.algorithm(), algorithm);
+        let bytes = signature.to_bytes();
+        assert_eq!(
+            AssertionSignature::from_bytes(algorithm, &bytes).unwrap(),
+            signature
+        );
+        match algorithm {
+            CredentialAlgorithm::Rs256 => {
+                let public_key = rsa_private_key(&key).unwrap().to_public_key();
+                rsa::pkcs1v15::VerifyingKey::<Sha256>::new(public_key)
+                    .verify(
+                        b"message",
+                        &rsa::pkcs1v15::Signature::try_from(&bytes[..]).unwrap(),
+                    )
+                    .unwrap();
+            }
+            _ => {
+                ed25519_signing_key(&key)
+                    .unwrap()
+                    .verifying_key()
+                    .verify(
+                        b"message",
+                        &ed25519_dalek::Signature::from_slice(&bytes).unwrap(),
+                    )
+                    .unwrap();
+            }
+        }
+        let public_key = CredentialPublicKey::new(&key, None).unwrap();
+        assert!(!public_key.cose_key.is_empty());
+    }
+}
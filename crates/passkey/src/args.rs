@@ -0,0 +1,162 @@
+//! The passkey guardian's contract args.
+//!
+//! Version 0, untagged, only holds ES256 signatures:
+//!
+//! ```text
+//! r: bytes32 ‖ s: bytes32 ‖ len ‖ authenticatorData ‖
+//! len ‖ clientDataJSONPre ‖ len ‖ clientDataJSONPost
+//! ```
+//!
+//! Version 1 prefixes the same fields with the tag `0x02 0x01`. Version 2,
+//! tagged `0x02 0x02`, holds signatures of any algorithm:
+//!
+//! ```text
+//! alg: int32 ‖ len ‖ signature ‖ len ‖ authenticatorData ‖
+//! len ‖ clientDataJSONPre ‖ len ‖ clientDataJSONPost
+//! ```
+//!
+//! `alg` is the COSE algorithm, -7 for ES256, -257 for RS256 and -8 for
+//! EdDSA. The signature is `r ‖ s` for ES256, the PKCS#1 v1.5 signature for
+//! RS256 and `R ‖ S` for EdDSA. Lengths are big-endian `u32`. Version 0 args
+//! start with `r`, so they can't be told apart from tagged ones and are
+//! decoded with `unpack_args_v0`.
+
+use anyhow::bail;
+use utils::args::{args_tag, put_bytes, ArgsReader, GuardianType};
+
+use crate::algorithm::{AssertionSignature, CredentialAlgorithm};
+
+/// The layout emitted for ES256 signatures by default.
+pub const ARGS_VERSION: u8 = 1;
+
+/// The first layout that holds RS256 and EdDSA signatures.
+pub const ARGS_VERSION_ANY_ALGORITHM: u8 = 2;
+
+/// The layout emitted by default for signatures of `algorithm`.
+pub fn default_args_version(algorithm: CredentialAlgorithm) -> u8 {
+    match algorithm {
+        CredentialAlgorithm::Es256 => ARGS_VERSION,
+        _ => ARGS_VERSION_ANY_ALGORITHM,
+    }
+}
+
+/// An assertion split around the challenge: `clientDataJSON` is
+/// `pre ‖ base64url(challenge) ‖ post`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssertionArgs {
+    pub signature: AssertionSignature,
+    pub authenticator_data: Vec<u8>,
+    pub client_data_json_pre: Vec<u8>,
+    pub client_data_json_post: Vec<u8>,
+}
+
+pub fn pack_args(args: &AssertionArgs, version: u8) -> anyhow::Result<Vec<u8>> {
+    let mut out = match version {
+        0 => vec![],
+        1 | 2 => args_tag(GuardianType::Passkey, version).to_vec(),
+        _ => bail!("unknown args version: {}", version),
+    };
+    match (version, &args.signature) {
+        (0 | 1, AssertionSignature::Es256 { r, s }) => {
+            out.extend(r);
+            out.extend(s);
+        }
+        (0 | 1, signature) => bail!(
+            "args version {} only holds ES256 signatures, not {}",
+            version,
+            signature.algorithm().name()
+        ),
+        (_, signature) => {
+            out.extend((signature.algorithm().cose() as i32).to_be_bytes());
+            put_bytes(&mut out, &signature.to_bytes());
+        }
+    }
+    put_bytes(&mut out, &args.authenticator_data);
+    put_bytes(&mut out, &args.client_data_json_pre);
+    put_bytes(&mut out, &args.client_data_json_post);
+    Ok(out)
+}
+
+/// Decodes tagged args, returning the version with the fields.
+pub fn unpack_args(args: &[u8]) -> anyhow::Result<(u8, AssertionArgs)> {
+    let mut reader = ArgsReader::new(args);
+    let version = reader.read_tag(GuardianType::Passkey)?;
+    let signature = match version {
+        1 => read_es256_signature(&mut reader)?,
+        2 => {
+            let alg = i32::from_be_bytes(reader.read_fixed(4)?.try_into()?);
+            let algorithm = [
+                CredentialAlgorithm::Es256,
+                CredentialAlgorithm::Rs256,
+                CredentialAlgorithm::EdDsa,
+            ]
+            .into_iter()
+            .find(|algorithm| algorithm.cose() as i32 == alg);
+            match algorithm {
+                Some(algorithm) => {
+                    AssertionSignature::from_bytes(algorithm, &reader.read_bytes()?)?
+                }
+                None => bail!("unknown signature algorithm: {}", alg),
+            }
+        }
+        _ => bail!("unknown args version: {}", version),
+    };
+    let unpacked = read_fields(&mut reader, signature)?;
+    reader.finish()?;
+    Ok((version, unpacked))
+}
+
+/// Decodes untagged version 0 args.
+pub fn unpack_args_v0(args: &[u8]) -> anyhow::Result<AssertionArgs> {
+    let mut reader = ArgsReader::new(args);
+    let signature = read_es256_signature(&mut reader)?;
+    let unpacked = read_fields(&mut reader, signature)?;
+    reader.finish()?;
+    Ok(unpacked)
+}
+
+fn read_es256_signature(reader: &mut ArgsReader) -> anyhow::Result<AssertionSignature> {
+    Ok(AssertionSignature::Es256 {
+        r: reader.read_fixed(32)?.try_into()?,
+        s: reader.read_fixed(32)?.try_into()?,
+    })
+}
+
+fn read_fields(
+    reader: &mut ArgsReader,
+    signature: AssertionSignature,
+) -> anyhow::Result<AssertionArgs> {
+    Ok(AssertionArgs {
+        signature,
+        authenticator_data: reader.read_bytes()?,
+        client_data_json_pre: reader.read_bytes()?,
+        client_data_json_post: reader.read_bytes()?,
+    })
+}
+
+#[test]
+fn test_args_versions() {
+    let mut args = AssertionArgs {
+        signature: AssertionSignature::Es256 {
+            r: [1; 32],
+            s: [2; 32],
+        },
+        authenticator_data: vec![3; 37],
+        client_data_json_pre: br#"{"type":"webauthn.get","challenge":""#.to_vec(),
+        client_data_json_post: br#"","origin":"https://passkey.test.com"}"#.to_vec(),
+    };
+    let v0 = pack_args(&args, 0).unwrap();
+    assert_eq!(unpack_args_v0(&v0).unwrap(), args);
+    let v1 = pack_args(&args, 1).unwrap();
+    assert_eq!(&v1[2..], &v0[..]);
+    assert_eq!(unpack_args(&v1).unwrap(), (1, args.clone()));
+    let v2 = pack_args(&args, 2).unwrap();
+    assert_eq!(&v2[..6], &[0x02, 0x02, 0xff, 0xff, 0xff, 0xf9]);
+    assert_eq!(unpack_args(&v2).unwrap(), (2, args.clone()));
+
+    args.signature = AssertionSignature::Rs256(vec![4; 256]);
+    assert!(pack_args(&args, 1).is_err());
+    let version = default_args_version(CredentialAlgorithm::Rs256);
+    let v2 = pack_args(&args, version).unwrap();
+    assert_eq!(unpack_args(&v2).unwrap(), (2, args));
+}
@@ -0,0 +1,331 @@
+//! Attestation statements (WebAuthn §8) on top of the `none` attestation
+//! passkey-rs produces, signed with the credential key or a locally
+//! generated attestation CA.
+
+use anyhow::anyhow;
+use coset::{cbor::Value, iana, CoseKey};
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    pkcs8::DecodePrivateKey,
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CustomExtension, DistinguishedName, DnType,
+    IsCa, KeyPair, PKCS_ECDSA_P256_SHA256,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    algorithm::{sign, CredentialAlgorithm},
+    authenticator_data::parse_authenticator_data,
+    public_key::p256_public_key,
+};
+
+/// id-fido-gen-ce-aaguid, which carries the AAGUID in attestation certs.
+const AAGUID_EXTENSION_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 45724, 1, 1, 4];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttestationFormat {
+    None,
+    /// `packed` signed with the credential key itself.
+    PackedSelf,
+    /// `packed` with an attestation cert chaining to a local root.
+    Packed,
+    FidoU2f,
+}
+
+pub fn parse_attestation_format(s: &str) -> Result<AttestationFormat, String> {
+    match s {
+        "none" => Ok(AttestationFormat::None),
+        "packed-self" => Ok(AttestationFormat::PackedSelf),
+        "packed" => Ok(AttestationFormat::Packed),
+        "fido-u2f" => Ok(AttestationFormat::FidoU2f),
+        _ => Err(format!("invalid attestation format: `{}`", s)),
+    }
+}
+
+impl AttestationFormat {
+    pub fn fmt(&self) -> &'static str {
+        match self {
+            AttestationFormat::None => "none",
+            AttestationFormat::PackedSelf | AttestationFormat::Packed => "packed",
+            AttestationFormat::FidoU2f => "fido-u2f",
+        }
+    }
+}
+
+/// An attestation CA made for one registration: a self-signed root, for
+/// `packed` an intermediate, and the attestation cert.
+pub struct AttestationCa {
+    pub root_pem: String,
+    /// The attestation cert first, as in `x5c`. The root isn't included.
+    pub chain: Vec<Vec<u8>>,
+    pub key: SigningKey,
+}
+
+fn distinguished_name(organizational_unit: &str, common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CountryName, "US");
+    name.push(DnType::OrganizationName, "Social Recovery Utils");
+    name.push(DnType::OrganizationalUnitName, organizational_unit);
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+fn ca_params(common_name: &str) -> CertificateParams {
+    let mut params = CertificateParams::new(Vec::<String>::new());
+    params.alg = &PKCS_ECDSA_P256_SHA256;
+    params.distinguished_name = distinguished_name("Attestation CA", common_name);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+}
+
+impl AttestationCa {
+    pub fn generate(format: AttestationFormat, aaguid: [u8; 16]) -> anyhow::Result<Self> {
+        let root = Certificate::from_params(ca_params("Mock Attestation Root"))?;
+
+        let mut params = CertificateParams::new(Vec::<String>::new());
+        params.alg = &PKCS_ECDSA_P256_SHA256;
+        // §8.2.1 requires this OU for `packed` attestation certs.
+        params.distinguished_name =
+            distinguished_name("Authenticator Attestation", "Mock Authenticator");
+        params.is_ca = IsCa::ExplicitNoCa;
+        if format == AttestationFormat::Packed {
+            // An OCTET STRING holding the AAGUID.
+            let mut content = vec![0x04, 0x10];
+            content.extend(aaguid);
+            params.custom_extensions = vec![CustomExtension::from_oid_content(
+                AAGUID_EXTENSION_OID,
+                content,
+            )];
+        }
+        let leaf = Certificate::from_params(params)?;
+
+        let chain = match format {
+            AttestationFormat::Packed => {
+                let intermediate =
+                    Certificate::from_params(ca_params("Mock Attestation Intermediate"))?;
+                vec![
+                    leaf.serialize_der_with_signer(&intermediate)?,
+                    intermediate.serialize_der_with_signer(&root)?,
+                ]
+            }
+            _ => vec![leaf.serialize_der_with_signer(&root)?],
+        };
+        Ok(AttestationCa {
+            root_pem: root.serialize_pem()?,
+            chain,
+            key: signing_key(leaf.get_key_pair())?,
+        })
+    }
+}
+
+fn signing_key(key_pair: &KeyPair) -> anyhow::Result<SigningKey> {
+    SigningKey::from_pkcs8_der(&key_pair.serialize_der())
+        .map_err(|e| anyhow!("invalid attestation key: {}", e))
+}
+
+fn der_signature(key: &SigningKey, message: &[u8]) -> Value {
+    let signature: Signature = key.sign(message);
+    Value::Bytes(signature.to_der().as_bytes().to_vec())
+}
+
+fn x5c(chain: &[Vec<u8>]) -> Value {
+    Value::Array(chain.iter().cloned().map(Value::Bytes).collect())
+}
+
+/// Builds the attestation object of `format` for a registration's
+/// `auth_data`, returning it with the root cert PEM if there is one.
+/// `packed` self attestation signs with `credential_key` in its algorithm,
+/// `fido-u2f` only takes ES256 credentials.
+pub fn attest(
+    format: AttestationFormat,
+    auth_data: &[u8],
+    client_data_json: &[u8],
+    credential_key: &CoseKey,
+) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+    let client_data_hash = Sha256::digest(client_data_json);
+    let signed = [auth_data, &client_data_hash[..]].concat();
+    let es256 = Value::from(iana::Algorithm::ES256 as i64);
+
+    let (att_stmt, root_pem) = match format {
+        AttestationFormat::None => (vec![], None),
+        AttestationFormat::PackedSelf => {
+            let alg = CredentialAlgorithm::of_key(credential_key)?.cose();
+            let sig = sign(credential_key, &signed)?.to_webauthn_bytes()?;
+            (
+                vec![("alg", Value::from(alg as i64)), ("sig", Value::Bytes(sig))],
+                None,
+            )
+        }
+        AttestationFormat::Packed => {
+            let attested = parse_authenticator_data(auth_data)?
+                .attested_credential_data
+                .ok_or_else(|| anyhow!("no attested credential data"))?;
+            let ca = AttestationCa::generate(format, attested.aaguid)?;
+            let sig = der_signature(&ca.key, &signed);
+            (
+                vec![("alg", es256), ("sig", sig), ("x5c", x5c(&ca.chain))],
+                Some(ca.root_pem),
+            )
+        }
+        AttestationFormat::FidoU2f => {
+            let auth_data_fields = parse_authenticator_data(auth_data)?;
+            let attested = auth_data_fields
+                .attested_credential_data
+                .ok_or_else(|| anyhow!("no attested credential data"))?;
+            let public_key = p256_public_key(&attested.credential_public_key)?;
+            let ca = AttestationCa::generate(format, attested.aaguid)?;
+            // §8.6: 0x00 ‖ rpIdHash ‖ clientDataHash ‖ credentialId ‖ publicKeyU2F
+            let verification_data = [
+                &[0x00][..],
+                &auth_data_fields.rp_id_hash[..],
+                &client_data_hash[..],
+                &attested.credential_id[..],
+                public_key.to_encoded_point(false).as_bytes(),
+            ]
+            .concat();
+            let sig = der_signature(&ca.key, &verification_data);
+            (
+                vec![("sig", sig), ("x5c", x5c(&ca.chain))],
+                Some(ca.root_pem),
+            )
+        }
+    };
+
+    let object = Value::Map(vec![
+        (Value::from("fmt"), Value::from(format.fmt())),
+        (
+            Value::from("attStmt"),
+            Value::Map(
+                att_stmt
+                    .into_iter()
+                    .map(|(key, value)| (Value::from(key), value))
+                    .collect(),
+            ),
+        ),
+        (Value::from("authData"), Value::Bytes(auth_data.to_vec())),
+    ]);
+    let mut out = vec![];
+    coset::cbor::ser::into_writer(&object, &mut out)
+        .map_err(|e| anyhow!("can't encode attestation object: {}", e))?;
+    Ok((out, root_pem))
+}
+
+#[test]
+fn test_attest() {
+    use crate::authenticator_data::{parse_attestation_object, FLAG_AT, FLAG_UP};
+    use coset::{CborSerializable, CoseKeyBuilder};
+    use p256::{
+        ecdsa::{signature::Verifier, VerifyingKey},
+        pkcs8::DecodePublicKey,
+    };
+    use x509_cert::{
+        der::{Decode, DecodePem, Encode},
+        Certificate,
+    };
+
+    let secret = p256::SecretKey::random(&mut rand::thread_rng());
+    let point = secret.public_key().to_encoded_point(false);
+    let key = CoseKeyBuilder::new_ec2_priv_key(
+        iana::EllipticCurve::P_256,
+        point.x().unwrap().to_vec(),
+        point.y().unwrap().to_vec(),
+        secret.to_bytes().to_vec(),
+    )
+    .algorithm(iana::Algorithm::ES256)
+    .build();
+    let mut auth_data = vec![7; 32];
+    auth_data.push(FLAG_UP | FLAG_AT);
+    auth_data.extend(0u32.to_be_bytes());
+    auth_data.extend([0; 16]);
+    auth_data.extend(2u16.to_be_bytes());
+    auth_data.extend([1, 2]);
+    auth_data.extend(crate::public_key::public_cose_key(&key).to_vec().unwrap());
+    let client_data_json = br#"{"type":"webauthn.create"}"#;
+
+    let (object, root) = attest(
+        AttestationFormat::PackedSelf,
+        &auth_data,
+        client_data_json,
+        &key,
+    )
+    .unwrap();
+    assert!(root.is_none());
+    let object = parse_attestation_object(&object).unwrap();
+    assert_eq!(object.fmt, "packed");
+    let sig = match &object.att_stmt {
+        Value::Map(entries) => entries[1].1.as_bytes().unwrap().clone(),
+        _ => panic!("attStmt is not a map"),
+    };
+    let signed = [&auth_data[..], &Sha256::digest(client_data_json)[..]].concat();
+    VerifyingKey::from(secret.public_key())
+        .verify(&signed, &Signature::from_der(&sig).unwrap())
+        .unwrap();
+
+    let public_key = |cert: &Certificate| {
+        let spki = cert
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .unwrap();
+        VerifyingKey::from(p256::PublicKey::from_public_key_der(&spki).unwrap())
+    };
+    // Checks that `issuer` signed `cert`.
+    let verify_cert = |cert: &Certificate, issuer: &Certificate| {
+        assert_eq!(cert.tbs_certificate.issuer, issuer.tbs_certificate.subject);
+        public_key(issuer)
+            .verify(
+                &cert.tbs_certificate.to_der().unwrap(),
+                &Signature::from_der(cert.signature.raw_bytes()).unwrap(),
+            )
+            .unwrap();
+    };
+    for format in [AttestationFormat::Packed, AttestationFormat::FidoU2f] {
+        let (object, root) = attest(format, &auth_data, client_data_json, &key).unwrap();
+        let root = Certificate::from_pem(root.unwrap()).unwrap();
+        let object = parse_attestation_object(&object).unwrap();
+        assert_eq!(object.fmt, format.fmt());
+        assert_eq!(object.auth_data, auth_data);
+        let stmt = |name: &str| match &object.att_stmt {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value.clone())
+                .unwrap(),
+            _ => panic!("attStmt is not a map"),
+        };
+        let chain: Vec<_> = stmt("x5c")
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cert| Certificate::from_der(cert.as_bytes().unwrap()).unwrap())
+            .collect();
+        // `packed` has an intermediate between the leaf and the root.
+        assert_eq!(chain.len() == 2, format == AttestationFormat::Packed);
+
+        // The leaf signs, and each cert is signed by the next up to the root.
+        let signed = match format {
+            AttestationFormat::Packed => signed.clone(),
+            _ => [
+                &[0x00][..],
+                &auth_data[..32],
+                &Sha256::digest(client_data_json)[..],
+                &[1, 2],
+                point.as_bytes(),
+            ]
+            .concat(),
+        };
+        public_key(&chain[0])
+            .verify(
+                &signed,
+                &Signature::from_der(stmt("sig").as_bytes().unwrap()).unwrap(),
+            )
+            .unwrap();
+        for pair in chain.windows(2) {
+            verify_cert(&pair[0], &pair[1]);
+        }
+        verify_cert(chain.last().unwrap(), &root);
+        verify_cert(&root, &root);
+    }
+}
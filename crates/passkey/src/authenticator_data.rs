@@ -0,0 +1,326 @@
+//! Decoding of attestation objects and authenticator data (WebAuthn §6.1).
+
+use std::io::Cursor;
+
+use anyhow::{anyhow, bail};
+use coset::{cbor::Value, AsCborValue, CoseKey};
+use serde::{Deserialize, Serialize};
+use utils::to_0x_hex;
+
+pub const FLAG_UP: u8 = 0x01;
+pub const FLAG_UV: u8 = 0x04;
+pub const FLAG_BE: u8 = 0x08;
+pub const FLAG_BS: u8 = 0x10;
+pub const FLAG_AT: u8 = 0x40;
+pub const FLAG_ED: u8 = 0x80;
+
+pub struct AttestedCredentialData {
+    pub aaguid: [u8; 16],
+    pub credential_id: Vec<u8>,
+    pub credential_public_key: CoseKey,
+    /// The COSE_Key as the authenticator encoded it.
+    pub credential_public_key_bytes: Vec<u8>,
+}
+
+pub struct AuthenticatorData {
+    pub rp_id_hash: [u8; 32],
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential_data: Option<AttestedCredentialData>,
+    /// The CBOR extensions map.
+    pub extensions: Option<Vec<u8>>,
+}
+
+pub struct AttestationObject {
+    pub fmt: String,
+    pub att_stmt: Value,
+    pub auth_data: Vec<u8>,
+}
+
+/// The flags and sign counter the generated authenticator data has,
+/// whatever the authenticator reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuthenticatorOptions {
+    pub user_present: bool,
+    pub user_verified: bool,
+    pub backup_eligible: bool,
+    pub backup_state: bool,
+    /// Replaces the authenticator's counter, may be zero or lower than the
+    /// last one.
+    pub sign_count: Option<u32>,
+}
+
+impl Default for AuthenticatorOptions {
+    fn default() -> Self {
+        AuthenticatorOptions {
+            user_present: true,
+            user_verified: true,
+            backup_eligible: false,
+            backup_state: false,
+            sign_count: None,
+        }
+    }
+}
+
+impl AuthenticatorOptions {
+    /// Rewrites the flags and counter of `auth_data`, returning whether it
+    /// changed and has to be signed again.
+    pub fn apply(&self, auth_data: &mut [u8]) -> bool {
+        let original = auth_data.to_vec();
+        let mut flags = auth_data[32] & !(FLAG_UP | FLAG_UV | FLAG_BE | FLAG_BS);
+        for (set, flag) in [
+            (self.user_present, FLAG_UP),
+            (self.user_verified, FLAG_UV),
+            (self.backup_eligible, FLAG_BE),
+            (self.backup_state, FLAG_BS),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        auth_data[32] = flags;
+        if let Some(sign_count) = self.sign_count {
+            auth_data[33..37].copy_from_slice(&sign_count.to_be_bytes());
+        }
+        auth_data != original.as_slice()
+    }
+}
+
+/// The fields of authenticator data as printed by the generators.
+#[derive(Serialize, Deserialize)]
+pub struct AuthenticatorDataFields {
+    pub rp_id_hash: String,
+    pub flags: u8,
+    pub user_present: bool,
+    pub user_verified: bool,
+    pub backup_eligible: bool,
+    pub backup_state: bool,
+    pub sign_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aaguid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<String>,
+}
+
+impl AuthenticatorData {
+    pub fn fields(&self) -> AuthenticatorDataFields {
+        let attested = self.attested_credential_data.as_ref();
+        AuthenticatorDataFields {
+            rp_id_hash: to_0x_hex(self.rp_id_hash),
+            flags: self.flags,
+            user_present: self.flags & FLAG_UP != 0,
+            user_verified: self.flags & FLAG_UV != 0,
+            backup_eligible: self.flags & FLAG_BE != 0,
+            backup_state: self.flags & FLAG_BS != 0,
+            sign_count: self.sign_count,
+            aaguid: attested.map(|data| to_0x_hex(data.aaguid)),
+            credential_id: attested.map(|data| to_0x_hex(&data.credential_id)),
+            credential_public_key: attested
+                .map(|data| to_0x_hex(&data.credential_public_key_bytes)),
+            extensions: self.extensions.as_ref().map(to_0x_hex),
+        }
+    }
+}
+
+/// Converts CBOR to JSON for printing, with byte strings as hex and
+/// non-text map keys as their JSON text.
+pub fn cbor_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Integer(integer) => match i64::try_from(*integer) {
+            Ok(integer) => integer.into(),
+            Err(_) => i128::from(*integer).to_string().into(),
+        },
+        Value::Bytes(bytes) => to_0x_hex(bytes).into(),
+        Value::Float(float) => (*float).into(),
+        Value::Text(text) => text.clone().into(),
+        Value::Bool(bool) => (*bool).into(),
+        Value::Null => serde_json::Value::Null,
+        Value::Tag(_, value) => cbor_to_json(value),
+        Value::Array(values) => values.iter().map(cbor_to_json).collect(),
+        Value::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| {
+                let key = match key {
+                    Value::Text(text) => text.clone(),
+                    key => cbor_to_json(key).to_string(),
+                };
+                (key, cbor_to_json(value))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn read_cbor(cursor: &mut Cursor<&[u8]>) -> anyhow::Result<(Value, Vec<u8>)> {
+    let start = cursor.position() as usize;
+    let value: Value =
+        coset::cbor::de::from_reader(&mut *cursor).map_err(|e| anyhow!("invalid CBOR: {}", e))?;
+    let end = cursor.position() as usize;
+    Ok((value, cursor.get_ref()[start..end].to_vec()))
+}
+
+fn take<'a>(data: &'a [u8], offset: &mut usize, len: usize) -> anyhow::Result<&'a [u8]> {
+    let end = *offset + len;
+    if end > data.len() {
+        bail!("authenticator data too short at offset {}", offset);
+    }
+    let bytes = &data[*offset..end];
+    *offset = end;
+    Ok(bytes)
+}
+
+pub fn parse_authenticator_data(data: &[u8]) -> anyhow::Result<AuthenticatorData> {
+    let mut offset = 0;
+    let rp_id_hash = take(data, &mut offset, 32)?.try_into()?;
+    let flags = take(data, &mut offset, 1)?[0];
+    let sign_count = u32::from_be_bytes(take(data, &mut offset, 4)?.try_into()?);
+
+    let attested_credential_data = if flags & FLAG_AT != 0 {
+        let aaguid = take(data, &mut offset, 16)?.try_into()?;
+        let len = u16::from_be_bytes(take(data, &mut offset, 2)?.try_into()?);
+        let credential_id = take(data, &mut offset, len as usize)?.to_vec();
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(offset as u64);
+        let (value, credential_public_key_bytes) = read_cbor(&mut cursor)?;
+        offset = cursor.position() as usize;
+        Some(AttestedCredentialData {
+            aaguid,
+            credential_id,
+            credential_public_key: CoseKey::from_cbor_value(value)
+                .map_err(|e| anyhow!("invalid COSE_Key: {:?}", e))?,
+            credential_public_key_bytes,
+        })
+    } else {
+        None
+    };
+
+    let extensions = if flags & FLAG_ED != 0 {
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(offset as u64);
+        let (_, bytes) = read_cbor(&mut cursor)?;
+        offset = cursor.position() as usize;
+        Some(bytes)
+    } else {
+        None
+    };
+
+    if offset != data.len() {
+        bail!(
+            "{} trailing bytes in authenticator data",
+            data.len() - offset
+        );
+    }
+    Ok(AuthenticatorData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        attested_credential_data,
+        extensions,
+    })
+}
+
+/// Replaces the COSE_Key in the attested credential data of `auth_data`,
+/// keeping the extensions after it.
+pub fn replace_credential_public_key(auth_data: &[u8], cose_key: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let attested = parse_authenticator_data(auth_data)?
+        .attested_credential_data
+        .ok_or_else(|| anyhow!("no attested credential data"))?;
+    let start = 37 + 16 + 2 + attested.credential_id.len();
+    let end = start + attested.credential_public_key_bytes.len();
+    Ok([&auth_data[..start], cose_key, &auth_data[end..]].concat())
+}
+
+/// Replaces the extensions of `auth_data` with the CBOR map `extensions`
+/// and sets the ED flag.
+pub fn set_extensions(auth_data: &[u8], extensions: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let parsed = parse_authenticator_data(auth_data)?;
+    let end = auth_data.len() - parsed.extensions.map_or(0, |bytes| bytes.len());
+    let mut out = [&auth_data[..end], extensions].concat();
+    out[32] |= FLAG_ED;
+    Ok(out)
+}
+
+pub fn parse_attestation_object(data: &[u8]) -> anyhow::Result<AttestationObject> {
+    let (value, _) = read_cbor(&mut Cursor::new(data))?;
+    let entries = match value {
+        Value::Map(entries) => entries,
+        _ => bail!("attestation object is not a map"),
+    };
+    let mut fmt = None;
+    let mut att_stmt = None;
+    let mut auth_data = None;
+    for (key, value) in entries {
+        match (key.as_text(), value) {
+            (Some("fmt"), Value::Text(text)) => fmt = Some(text),
+            (Some("attStmt"), value) => att_stmt = Some(value),
+            (Some("authData"), Value::Bytes(bytes)) => auth_data = Some(bytes),
+            _ => {}
+        }
+    }
+    Ok(AttestationObject {
+        fmt: fmt.ok_or_else(|| anyhow!("attestation object is missing fmt"))?,
+        att_stmt: att_stmt.ok_or_else(|| anyhow!("attestation object is missing attStmt"))?,
+        auth_data: auth_data.ok_or_else(|| anyhow!("attestation object is missing authData"))?,
+    })
+}
+
+#[test]
+fn test_parse_attested_credential_data() {
+    use crate::public_key::{p256_public_key, CredentialPublicKey};
+    use coset::{iana, CborSerializable, CoseKeyBuilder};
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+    let secret = p256::SecretKey::random(&mut rand::thread_rng());
+    let point = secret.public_key().to_encoded_point(false);
+    let key = CoseKeyBuilder::new_ec2_pub_key(
+        iana::EllipticCurve::P_256,
+        point.x().unwrap().to_vec(),
+        point.y().unwrap().to_vec(),
+    )
+    .algorithm(iana::Algorithm::ES256)
+    .build();
+    let cose = key.clone().to_vec().unwrap();
+
+    let mut data = vec![7; 32];
+    data.push(FLAG_UP | FLAG_UV | FLAG_AT);
+    data.extend(5u32.to_be_bytes());
+    data.extend([0; 16]);
+    data.extend(4u16.to_be_bytes());
+    data.extend([1, 2, 3, 4]);
+    data.extend(&cose);
+
+    let parsed = parse_authenticator_data(&data).unwrap();
+    assert_eq!(parsed.sign_count, 5);
+    let attested = parsed.attested_credential_data.unwrap();
+    assert_eq!(attested.credential_id, [1, 2, 3, 4]);
+    assert_eq!(attested.credential_public_key_bytes, cose);
+    assert_eq!(
+        p256_public_key(&attested.credential_public_key).unwrap(),
+        secret.public_key()
+    );
+    let public_key = CredentialPublicKey::new(&attested.credential_public_key, None).unwrap();
+    assert_eq!(public_key.cose_key, utils::to_0x_hex(&cose));
+
+    let replaced = replace_credential_public_key(&data, &[0xa0]).unwrap();
+    assert_eq!(replaced.len(), data.len() - cose.len() + 1);
+    assert_eq!(
+        replaced[..replaced.len() - 1],
+        data[..data.len() - cose.len()]
+    );
+
+    // {"hmac-secret": true}
+    let extensions = [&[0xa1, 0x6b][..], b"hmac-secret", &[0xf5]].concat();
+    let extended = set_extensions(&data, &extensions).unwrap();
+    let parsed = parse_authenticator_data(&extended).unwrap();
+    assert_eq!(parsed.flags & FLAG_ED, FLAG_ED);
+    assert_eq!(parsed.extensions.unwrap(), extensions);
+    assert_eq!(set_extensions(&extended, &extensions).unwrap(), extended);
+
+    data.push(0);
+    assert!(parse_authenticator_data(&data).is_err());
+}
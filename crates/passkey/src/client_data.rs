@@ -0,0 +1,175 @@
+//! clientDataJSON shapes browsers emit besides the one of passkey-rs, and
+//! locating the challenge in any of them.
+
+use std::ops::Range;
+
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+
+/// The member Chrome adds to some clientDataJSON to stop RPs from comparing
+/// it against a template.
+pub const CHROME_EXTRA_KEY: (&str, &str) = (
+    "other_keys_can_be_added_here",
+    "do not compare clientDataJSON against a template. See https://goo.gl/yabPex",
+);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientDataOptions {
+    /// `crossOrigin`, false unless set.
+    pub cross_origin: bool,
+    pub top_origin: Option<String>,
+    /// Adds `CHROME_EXTRA_KEY`.
+    pub chrome_extra_key: bool,
+    /// Members appended after the others.
+    pub extra_fields: Vec<(String, Value)>,
+    /// Members listed here come first, in this order.
+    pub key_order: Vec<String>,
+}
+
+impl ClientDataOptions {
+    pub fn is_default(&self) -> bool {
+        *self == ClientDataOptions::default()
+    }
+}
+
+/// Parses a `key=value` extra member. Values that aren't JSON are strings.
+pub fn parse_extra_field(s: &str) -> Result<(String, Value), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid extra field, expected key=value: `{}`", s))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+    Ok((key.to_string(), value))
+}
+
+/// Builds a clientDataJSON with the members in the order of `options`.
+pub fn build_client_data_json(
+    ty: &str,
+    challenge: &str,
+    origin: &str,
+    options: &ClientDataOptions,
+) -> String {
+    let mut members = vec![
+        ("type".to_string(), Value::from(ty)),
+        ("challenge".to_string(), Value::from(challenge)),
+        ("origin".to_string(), Value::from(origin)),
+        ("crossOrigin".to_string(), Value::from(options.cross_origin)),
+    ];
+    if let Some(top_origin) = &options.top_origin {
+        members.push(("topOrigin".to_string(), Value::from(top_origin.as_str())));
+    }
+    if options.chrome_extra_key {
+        members.push((
+            CHROME_EXTRA_KEY.0.to_string(),
+            Value::from(CHROME_EXTRA_KEY.1),
+        ));
+    }
+    members.extend(options.extra_fields.iter().cloned());
+    // A stable sort keeps the remaining members in their default order.
+    members.sort_by_key(|(key, _)| {
+        options
+            .key_order
+            .iter()
+            .position(|k| k == key)
+            .unwrap_or(options.key_order.len())
+    });
+
+    let members: Vec<_> = members
+        .iter()
+        .map(|(key, value)| format!("{}:{}", Value::from(key.as_str()), value))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+/// Rebuilds a clientDataJSON with another origin and shape, keeping its
+/// type and challenge.
+pub fn rebuild_client_data_json(
+    client_data_json: &str,
+    origin: &str,
+    options: &ClientDataOptions,
+) -> anyhow::Result<String> {
+    let parsed: Value = serde_json::from_str(client_data_json)?;
+    let member = |key: &str| {
+        parsed[key]
+            .as_str()
+            .ok_or_else(|| anyhow!("clientDataJSON has no {}", key))
+    };
+    Ok(build_client_data_json(
+        member("type")?,
+        member("challenge")?,
+        origin,
+        options,
+    ))
+}
+
+/// The byte range of the `challenge` member's value, without its quotes.
+/// Only members of the top-level object are looked at, so a `challenge`
+/// inside another member's value doesn't match.
+pub fn challenge_range(client_data_json: &str) -> anyhow::Result<Range<usize>> {
+    let bytes = client_data_json.as_bytes();
+    let mut depth = 0;
+    let mut key: Option<&str> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len() && bytes[end] != b'"' {
+                    if bytes[end] == b'\\' {
+                        end += 1;
+                    }
+                    end += 1;
+                }
+                if end >= bytes.len() {
+                    bail!("unterminated string at offset {}", i);
+                }
+                i = end + 1;
+                if depth == 1 {
+                    if client_data_json[i..].trim_start().starts_with(':') {
+                        key = Some(&client_data_json[start..end]);
+                    } else if key == Some("challenge") {
+                        return Ok(start..end);
+                    } else {
+                        key = None;
+                    }
+                }
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    bail!("clientDataJSON has no challenge")
+}
+
+#[test]
+fn test_challenge_range() {
+    let options = ClientDataOptions {
+        cross_origin: true,
+        top_origin: Some("https://top.test.com".to_string()),
+        chrome_extra_key: true,
+        extra_fields: vec![
+            ("note".to_string(), Value::from(r#"{"challenge":"fake"}"#)),
+            (
+                "nested".to_string(),
+                serde_json::json!({"challenge": "fake"}),
+            ),
+        ],
+        key_order: vec!["nested".to_string(), "origin".to_string()],
+    };
+    let json = build_client_data_json("webauthn.get", "Y2hhbGxlbmdl", "https://test.com", &options);
+    assert!(
+        json.starts_with(r#"{"nested":{"challenge":"fake"},"origin":"https://test.com","type""#)
+    );
+    assert!(json.contains(r#""crossOrigin":true"#));
+    let range = challenge_range(&json).unwrap();
+    assert_eq!(&json[range], "Y2hhbGxlbmdl");
+
+    let rebuilt = rebuild_client_data_json(&json, "android:apk-key-hash:abc", &Default::default());
+    assert_eq!(
+        rebuilt.unwrap(),
+        r#"{"type":"webauthn.get","challenge":"Y2hhbGxlbmdl","origin":"android:apk-key-hash:abc","crossOrigin":false}"#
+    );
+}
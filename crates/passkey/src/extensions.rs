@@ -0,0 +1,202 @@
+//! The credProps, prf and largeBlob extensions. passkey-rs ignores
+//! extensions, so their outputs are made here: the client extension results
+//! are printed and the authenticator extension outputs are added to the
+//! authenticator data, which is then signed again.
+
+use anyhow::{anyhow, bail};
+use coset::cbor::Value;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use utils::to_0x_hex;
+
+use crate::store::ExtensionState;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtensionOptions {
+    /// Registration only.
+    pub cred_props: bool,
+    /// Registers the credential with a PRF secret, hmac-secret in CTAP.
+    pub prf: bool,
+    /// The `first` and `second` PRF inputs to evaluate.
+    pub prf_inputs: Vec<Vec<u8>>,
+    /// Asks for support when registering and reads the blob when signing.
+    pub large_blob: bool,
+    /// The blob to write when signing.
+    pub large_blob_write: Option<Vec<u8>>,
+}
+
+impl ExtensionOptions {
+    pub fn is_default(&self) -> bool {
+        *self == ExtensionOptions::default()
+    }
+}
+
+/// The results of the requested extensions.
+#[derive(Default)]
+pub struct ExtensionOutputs {
+    /// `getClientExtensionResults()`.
+    pub client: serde_json::Map<String, serde_json::Value>,
+    /// The CBOR map of the authenticator data, if any.
+    pub authenticator: Option<Vec<u8>>,
+}
+
+/// The salt hmac-secret is evaluated with for a PRF input (WebAuthn §10.1.4).
+fn prf_salt(input: &[u8]) -> Vec<u8> {
+    Sha256::new()
+        .chain_update(b"WebAuthn PRF")
+        .chain_update([0x00])
+        .chain_update(input)
+        .finalize()
+        .to_vec()
+}
+
+/// The PRF outputs of `inputs`, each `HMAC-SHA-256(CredRandom, salt)`.
+fn prf_outputs(secret: &[u8], inputs: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
+    if inputs.len() > 2 {
+        bail!("at most two PRF inputs, got {}", inputs.len());
+    }
+    Ok(inputs
+        .iter()
+        .map(|input| {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+            mac.update(&prf_salt(input));
+            mac.finalize().into_bytes().to_vec()
+        })
+        .collect())
+}
+
+fn prf_results(outputs: &[Vec<u8>]) -> serde_json::Value {
+    let mut results = serde_json::Map::new();
+    for (name, output) in ["first", "second"].iter().zip(outputs) {
+        results.insert(name.to_string(), to_0x_hex(output).into());
+    }
+    results.into()
+}
+
+fn encode_extensions(entries: Vec<(&str, Value)>) -> anyhow::Result<Option<Vec<u8>>> {
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let map = Value::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::from(key), value))
+            .collect(),
+    );
+    let mut out = vec![];
+    coset::cbor::ser::into_writer(&map, &mut out)
+        .map_err(|e| anyhow!("can't encode extensions: {}", e))?;
+    Ok(Some(out))
+}
+
+/// Runs the extensions of a registration, setting up `state` for later
+/// assertions.
+pub fn registration_extensions(
+    options: &ExtensionOptions,
+    state: &mut ExtensionState,
+) -> anyhow::Result<ExtensionOutputs> {
+    let mut outputs = ExtensionOutputs::default();
+    let mut authenticator = vec![];
+    if options.cred_props {
+        // Passkeys are always discoverable.
+        outputs
+            .client
+            .insert("credProps".to_string(), json!({ "rk": true }));
+    }
+    if options.prf || !options.prf_inputs.is_empty() {
+        let secret = rand::random::<[u8; 32]>().to_vec();
+        let mut prf = json!({ "enabled": true });
+        if !options.prf_inputs.is_empty() {
+            prf["results"] = prf_results(&prf_outputs(&secret, &options.prf_inputs)?);
+        }
+        outputs.client.insert("prf".to_string(), prf);
+        authenticator.push(("hmac-secret", Value::Bool(true)));
+        state.prf_secret = Some(secret);
+    }
+    if options.large_blob {
+        outputs
+            .client
+            .insert("largeBlob".to_string(), json!({ "supported": true }));
+    }
+    outputs.authenticator = encode_extensions(authenticator)?;
+    Ok(outputs)
+}
+
+/// Runs the extensions of an assertion. The hmac-secret output in the
+/// authenticator data isn't encrypted, as there's no platform to share a
+/// key with.
+pub fn authentication_extensions(
+    options: &ExtensionOptions,
+    state: &mut ExtensionState,
+) -> anyhow::Result<ExtensionOutputs> {
+    let mut outputs = ExtensionOutputs::default();
+    let mut authenticator = vec![];
+    if options.prf || !options.prf_inputs.is_empty() {
+        let secret = state
+            .prf_secret
+            .as_ref()
+            .ok_or_else(|| anyhow!("the credential was registered without PRF"))?;
+        if options.prf_inputs.is_empty() {
+            bail!("PRF needs an input to evaluate");
+        }
+        let prf_outputs = prf_outputs(secret, &options.prf_inputs)?;
+        outputs.client.insert(
+            "prf".to_string(),
+            json!({ "results": prf_results(&prf_outputs) }),
+        );
+        authenticator.push(("hmac-secret", Value::Bytes(prf_outputs.concat())));
+    }
+    if let Some(blob) = &options.large_blob_write {
+        state.large_blob = Some(blob.clone());
+        outputs
+            .client
+            .insert("largeBlob".to_string(), json!({ "written": true }));
+    } else if options.large_blob {
+        let large_blob = match &state.large_blob {
+            Some(blob) => json!({ "blob": to_0x_hex(blob) }),
+            None => json!({}),
+        };
+        outputs.client.insert("largeBlob".to_string(), large_blob);
+    }
+    outputs.authenticator = encode_extensions(authenticator)?;
+    Ok(outputs)
+}
+
+#[test]
+fn test_prf() {
+    let options = ExtensionOptions {
+        prf: true,
+        prf_inputs: vec![b"recovery".to_vec()],
+        large_blob: true,
+        ..Default::default()
+    };
+    let mut state = ExtensionState::default();
+    let registered = registration_extensions(&options, &mut state).unwrap();
+    assert_eq!(registered.client["prf"]["enabled"], true);
+    assert_eq!(registered.client["largeBlob"]["supported"], true);
+
+    let asserted = authentication_extensions(&options, &mut state).unwrap();
+    // The same input gives the same output for the credential.
+    assert_eq!(
+        asserted.client["prf"]["results"],
+        registered.client["prf"]["results"]
+    );
+    assert_eq!(asserted.client["largeBlob"], json!({}));
+    let extensions: Value =
+        coset::cbor::de::from_reader(&asserted.authenticator.unwrap()[..]).unwrap();
+    let output = match extensions {
+        Value::Map(entries) => entries[0].1.as_bytes().unwrap().clone(),
+        _ => panic!("extensions are not a map"),
+    };
+    assert_eq!(output.len(), 32);
+
+    let written = ExtensionOptions {
+        large_blob_write: Some(vec![1, 2, 3]),
+        ..Default::default()
+    };
+    authentication_extensions(&written, &mut state).unwrap();
+    let read = authentication_extensions(&options, &mut state).unwrap();
+    assert_eq!(read.client["largeBlob"]["blob"], "0x010203");
+}
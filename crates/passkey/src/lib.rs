@@ -1,20 +1,48 @@
 //! Sample App for Passkeys
 
+use algorithm::{sign, AssertionSignature, CredentialAlgorithm};
+use anyhow::bail;
+use args::{default_args_version, pack_args, AssertionArgs, ARGS_VERSION_ANY_ALGORITHM};
 use base64::Engine;
-use p256::ecdsa;
+use p256::{ecdsa, elliptic_curve::scalar::IsHigh};
 use passkey::{
     authenticator::{Authenticator, UserValidationMethod},
     client::{Client, WebauthnError},
-    types::{ctap2::*, rand::random_vec, webauthn::*, Bytes, Passkey},
+    types::{ctap2::*, rand::random_vec, webauthn::*, Bytes},
+};
+use public_key::{
+    p256_public_key, p256_public_key_from_spki, public_cose_key, CredentialPublicKey,
 };
 
-use coset::iana;
+use attestation::{attest, AttestationFormat};
+use authenticator_data::{
+    cbor_to_json, parse_attestation_object, parse_authenticator_data,
+    replace_credential_public_key, set_extensions, AuthenticatorDataFields, AuthenticatorOptions,
+};
+use client_data::{challenge_range, rebuild_client_data_json, ClientDataOptions};
+use coset::{iana, CborSerializable, CoseKey};
+use extensions::{authentication_extensions, registration_extensions, ExtensionOptions};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use store::FileCredentialStore;
 use url::Url;
 use utils::{from_0x_hex, to_0x_hex};
 
-// MyUserValidationMethod is a stub impl of the UserValidationMethod trait, used later.
-struct MyUserValidationMethod {}
+pub mod algorithm;
+pub mod args;
+pub mod attestation;
+pub mod authenticator_data;
+pub mod client_data;
+pub mod extensions;
+pub mod public_key;
+pub mod store;
+
+// MyUserValidationMethod answers the authenticator's user checks. The client
+// always asks for user presence and gives up if it's denied, so UP=0 is made
+// by rewriting the authenticator data instead.
+struct MyUserValidationMethod {
+    verification: bool,
+}
 #[async_trait::async_trait]
 impl UserValidationMethod for MyUserValidationMethod {
     async fn check_user_presence(&self) -> bool {
@@ -22,11 +50,11 @@ impl UserValidationMethod for MyUserValidationMethod {
     }
 
     async fn check_user_verification(&self) -> bool {
-        true
+        self.verification
     }
 
     fn is_verification_enabled(&self) -> Option<bool> {
-        Some(true)
+        Some(self.verification)
     }
 
     fn is_presence_enabled(&self) -> bool {
@@ -34,145 +62,657 @@ impl UserValidationMethod for MyUserValidationMethod {
     }
 }
 
-// Example of how to set up, register and authenticate with a `Client`.
+/// A credential the client registers before authenticating.
+struct Registration {
+    challenge: Bytes,
+    parameters: PublicKeyCredentialParameters,
+    user_entity: PublicKeyCredentialUserEntity,
+    attestation: AttestationConveyancePreference,
+}
+
+// Sets up a `Client` over `store`, registers a credential if asked to and
+// authenticates with `challenge` if there is one.
 async fn client_setup(
-    challenge_bytes_from_rp: Bytes,
-    parameters_from_rp: PublicKeyCredentialParameters,
+    store: FileCredentialStore,
+    rp_id: &str,
     origin: &Url,
-    user_entity: PublicKeyCredentialUserEntity,
-) -> Result<(CreatedPublicKeyCredential, AuthenticatedPublicKeyCredential), WebauthnError> {
+    registration: Option<Registration>,
+    challenge: Option<Bytes>,
+    authenticator: &AuthenticatorOptions,
+) -> Result<
+    (
+        Option<CreatedPublicKeyCredential>,
+        Option<AuthenticatedPublicKeyCredential>,
+    ),
+    WebauthnError,
+> {
     // First create an Authenticator for the Client to use.
     let my_aaguid = Aaguid::new_empty();
-    let user_validation_method = MyUserValidationMethod {};
-    // Create the CredentialStore for the Authenticator.
-    // Option<Passkey> is the simplest possible implementation of CredentialStore
-    let store: Option<Passkey> = None;
+    let user_validation_method = MyUserValidationMethod {
+        verification: authenticator.user_verified,
+    };
     let my_authenticator = Authenticator::new(my_aaguid, store, user_validation_method);
 
     // Create the Client
     // If you are creating credentials, you need to declare the Client as mut
     let mut my_client = Client::new(my_authenticator);
 
-    // The following values, provided as parameters to this function would usually be
-    // retrieved from a Relying Party according to the context of the application.
-    let request = CredentialCreationOptions {
-        public_key: PublicKeyCredentialCreationOptions {
-            rp: PublicKeyCredentialRpEntity {
-                id: None, // Leaving the ID as None means use the effective domain
-                name: origin.domain().unwrap().into(),
-            },
-            user: user_entity,
-            challenge: challenge_bytes_from_rp.clone(),
-            pub_key_cred_params: vec![parameters_from_rp],
-            timeout: None,
-            exclude_credentials: None,
-            authenticator_selection: None,
-            attestation: AttestationConveyancePreference::None,
-            attestation_formats: None,
-            extensions: None,
-        },
+    let created = match registration {
+        Some(registration) => {
+            let request = CredentialCreationOptions {
+                public_key: PublicKeyCredentialCreationOptions {
+                    rp: PublicKeyCredentialRpEntity {
+                        id: Some(rp_id.to_string()),
+                        name: rp_id.to_string(),
+                    },
+                    user: registration.user_entity,
+                    challenge: registration.challenge,
+                    pub_key_cred_params: vec![registration.parameters],
+                    timeout: None,
+                    exclude_credentials: None,
+                    authenticator_selection: None,
+                    attestation: registration.attestation,
+                    attestation_formats: None,
+                    extensions: None,
+                },
+            };
+            Some(my_client.register(origin, request, None).await?)
+        }
+        None => None,
     };
 
-    // Now create the credential.
-    let my_webauthn_credential = my_client.register(origin, request, None).await?;
-
-    // Let's try and authenticate.
-    // Create a challenge that would usually come from the RP.
-    // let challenge_bytes_from_rp: Bytes = random_vec(32).into();
-    // Now try and authenticate
-    let credential_request = CredentialRequestOptions {
-        public_key: PublicKeyCredentialRequestOptions {
-            challenge: challenge_bytes_from_rp,
-            timeout: None,
-            rp_id: Some(String::from(origin.domain().unwrap())),
-            allow_credentials: None,
-            user_verification: UserVerificationRequirement::default(),
-            attestation: AttestationConveyancePreference::None,
-            attestation_formats: None,
-            extensions: None,
-        },
+    let authenticated = match challenge {
+        Some(challenge) => {
+            let credential_request = CredentialRequestOptions {
+                public_key: PublicKeyCredentialRequestOptions {
+                    challenge,
+                    timeout: None,
+                    rp_id: Some(rp_id.to_string()),
+                    allow_credentials: None,
+                    user_verification: match authenticator.user_verified {
+                        true => UserVerificationRequirement::default(),
+                        false => UserVerificationRequirement::Discouraged,
+                    },
+                    attestation: AttestationConveyancePreference::None,
+                    attestation_formats: None,
+                    extensions: None,
+                },
+            };
+            Some(
+                my_client
+                    .authenticate(origin, credential_request, None)
+                    .await?,
+            )
+        }
+        None => None,
     };
 
-    let authenticated_cred = my_client
-        .authenticate(origin, credential_request, None)
-        .await?;
+    Ok((created, authenticated))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyArgs {
+    #[serde(flatten)]
+    pub public_key: CredentialPublicKey,
+    pub credential_id: String,
+    pub authenticator_data: AuthenticatorDataFields,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_extension_results: Option<serde_json::Value>,
+    pub args_version: u8,
+    pub args: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyCredential {
+    pub credential: String,
+    pub credential_id: String,
+    pub rp_id: String,
+    pub origin: String,
+    #[serde(flatten)]
+    pub public_key: CredentialPublicKey,
+    /// The registration response.
+    pub client_data_json: String,
+    pub attestation_object: String,
+    /// The attestation object's fields.
+    pub fmt: String,
+    pub att_stmt: serde_json::Value,
+    pub authenticator_data: AuthenticatorDataFields,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_extension_results: Option<serde_json::Value>,
+    /// The PEM root cert the `x5c` chain leads to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation_root: Option<String>,
+}
+
+/// How the passkey ceremonies are run and what the authenticator and
+/// client report.
+#[derive(Clone, Debug)]
+pub struct PasskeyOptions {
+    /// Defaults to the rp id of the saved credential, then to the host of
+    /// `origin`.
+    pub rp_id: Option<String>,
+    /// A web origin, which may have a port or be a subdomain of the rp id, or
+    /// an Android app origin `android:apk-key-hash:<hash>`. Defaults to
+    /// `https://<rp id>`.
+    pub origin: Option<String>,
+    pub user_name: String,
+    pub user_display_name: String,
+    /// Random unless set.
+    pub user_id: Option<Vec<u8>>,
+    /// The algorithm of registered credentials.
+    pub alg: CredentialAlgorithm,
+    /// The `s` of ES256 signatures.
+    pub signature_s: SignatureS,
+    /// The attestation of `passkey register`.
+    pub attestation: AttestationFormat,
+    pub authenticator: AuthenticatorOptions,
+    pub client_data: ClientDataOptions,
+    pub extensions: ExtensionOptions,
+}
 
-    Ok((my_webauthn_credential, authenticated_cred))
+/// Which of the two valid `s` values, `s` and `n - s`, an assertion
+/// signature has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureS {
+    /// Whichever the authenticator produced.
+    AsSigned,
+    /// `s <= n / 2`, as required by e.g. the RIP-7212 precompile.
+    Low,
+    High,
 }
 
-fn index_of_sub_array(array: &[u8], sub_array: &[u8], start: usize) -> Option<usize> {
-    if sub_array.is_empty() {
-        return None;
+fn adjust_s(signature: ecdsa::Signature, form: SignatureS) -> ecdsa::Signature {
+    let high = bool::from(signature.s().is_high());
+    match form {
+        SignatureS::Low if high => {}
+        SignatureS::High if !high => {}
+        _ => return signature,
     }
-    array[start..]
-        .windows(sub_array.len())
-        .position(|window| window == sub_array)
-        .map(|v| v + start)
+    ecdsa::Signature::from_scalars(signature.r(), -signature.s()).unwrap()
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct PasskeyArgs {
-    pub q_x: String,
-    pub q_y: String,
-    pub args: String,
+impl Default for PasskeyOptions {
+    fn default() -> Self {
+        PasskeyOptions {
+            rp_id: None,
+            origin: None,
+            user_name: "passkey@example.org".to_string(),
+            user_display_name: "Passkey Tester".to_string(),
+            user_id: None,
+            alg: CredentialAlgorithm::Es256,
+            signature_s: SignatureS::AsSigned,
+            attestation: AttestationFormat::None,
+            authenticator: AuthenticatorOptions::default(),
+            client_data: ClientDataOptions::default(),
+            extensions: ExtensionOptions::default(),
+        }
+    }
+}
+
+/// The rp id and origin of `PasskeyOptions` after defaults.
+struct RelyingParty {
+    id: String,
+    origin: String,
+    /// The web origin the passkey-rs client runs the ceremonies for. If it
+    /// isn't `origin`, the clientDataJSON is rewritten and signed again.
+    client_origin: Url,
+}
+
+impl PasskeyOptions {
+    fn relying_party(&self, stored_rp_id: Option<&str>) -> RelyingParty {
+        let web_origin = self
+            .origin
+            .as_deref()
+            .map(|origin| Url::parse(origin).expect("invalid origin"))
+            .filter(|url| matches!(url.scheme(), "https" | "http"));
+        let id = self
+            .rp_id
+            .clone()
+            .or_else(|| stored_rp_id.map(String::from))
+            .or_else(|| {
+                web_origin
+                    .as_ref()
+                    .and_then(|url| url.domain().map(String::from))
+            })
+            .unwrap_or_else(|| "passkey.test.com".to_string());
+        let client_origin =
+            web_origin.unwrap_or_else(|| Url::parse(&format!("https://{}", id)).unwrap());
+        let origin = match &self.origin {
+            // App origins are kept as given.
+            Some(origin) if !origin.starts_with("http") => origin.clone(),
+            _ => client_origin.origin().ascii_serialization(),
+        };
+        RelyingParty {
+            id,
+            origin,
+            client_origin,
+        }
+    }
+
+    fn registration(&self, challenge: Bytes) -> Registration {
+        Registration {
+            challenge,
+            // The authenticator only makes ES256 keys, other algorithms
+            // replace its key after the registration.
+            parameters: PublicKeyCredentialParameters {
+                ty: PublicKeyCredentialType::PublicKey,
+                alg: iana::Algorithm::ES256,
+            },
+            user_entity: PublicKeyCredentialUserEntity {
+                id: self
+                    .user_id
+                    .clone()
+                    .unwrap_or_else(|| random_vec(32))
+                    .into(),
+                display_name: self.user_display_name.clone(),
+                name: self.user_name.clone(),
+            },
+            attestation: match self.attestation {
+                AttestationFormat::None => AttestationConveyancePreference::None,
+                _ => AttestationConveyancePreference::Direct,
+            },
+        }
+    }
+}
+
+/// The credential id and public key of a registration, decoded from the
+/// attested credential data and checked against the SPKI the client reports.
+fn registered_public_key(
+    created_cred: &CreatedPublicKeyCredential,
+) -> anyhow::Result<(Vec<u8>, CredentialPublicKey)> {
+    let attestation_object = parse_attestation_object(&created_cred.response.attestation_object)?;
+    let auth_data = parse_authenticator_data(&attestation_object.auth_data)?;
+    let attested = auth_data
+        .attested_credential_data
+        .ok_or_else(|| anyhow::anyhow!("no attested credential data"))?;
+    if let Some(spki) = &created_cred.response.public_key {
+        if p256_public_key_from_spki(spki)? != p256_public_key(&attested.credential_public_key)? {
+            anyhow::bail!("the SPKI and the COSE_Key differ");
+        }
+    }
+    let public_key = CredentialPublicKey::new(
+        &attested.credential_public_key,
+        Some(attested.credential_public_key_bytes),
+    )?;
+    Ok((attested.credential_id, public_key))
+}
+
+impl PasskeyOptions {
+    /// The clientDataJSON the client reported, rebuilt if the origin or its
+    /// shape differ, and whether it was.
+    fn client_data_json(&self, rp: &RelyingParty, reported: &[u8]) -> (String, bool) {
+        let reported = String::from_utf8(reported.to_vec()).unwrap();
+        if rp.origin == rp.client_origin.origin().ascii_serialization()
+            && self.client_data.is_default()
+        {
+            return (reported, false);
+        }
+        let rebuilt = rebuild_client_data_json(&reported, &rp.origin, &self.client_data).unwrap();
+        (rebuilt, true)
+    }
+}
+
+/// Signs `authenticatorData ‖ SHA-256(clientDataJSON)` with the private key
+/// of the passkey.
+fn resign(
+    key: &CoseKey,
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+) -> anyhow::Result<AssertionSignature> {
+    let mut message = authenticator_data.to_vec();
+    message.extend(Sha256::digest(client_data_json));
+    sign(key, &message)
 }
 
-pub async fn generate_args(challenge: String) -> String {
-    let rp_url = Url::parse("https://passkey.test.com").expect("Should Parse");
-    let user_entity = PublicKeyCredentialUserEntity {
-        id: random_vec(32).into(),
-        display_name: "Passkey Tester".into(),
-        name: "passkey@example.org".into(),
+/// Gives a new credential a key of `alg` in place of the authenticator's
+/// ES256 key.
+fn replace_credential_key(
+    store: &mut FileCredentialStore,
+    alg: CredentialAlgorithm,
+) -> anyhow::Result<CoseKey> {
+    let key = alg.generate_key()?;
+    store.set_credential_key(key.clone())?;
+    Ok(key)
+}
+
+/// Registers a new credential and saves it to `credential`.
+pub async fn register_credential(
+    credential: String,
+    force: bool,
+    options: &PasskeyOptions,
+) -> anyhow::Result<String> {
+    let rp = options.relying_party(None);
+    let mut store = FileCredentialStore::create(&credential, force)?;
+    let (created_cred, _) = client_setup(
+        store.clone(),
+        &rp.id,
+        &rp.client_origin,
+        Some(options.registration(random_vec(32).into())),
+        None,
+        &options.authenticator,
+    )
+    .await
+    .map_err(|e| match store.take_save_error() {
+        Some(error) => anyhow::anyhow!(error),
+        None => anyhow::anyhow!("registration failed: {:?}", e),
+    })?;
+    let created_cred = created_cred.unwrap();
+    let (credential_id, mut public_key) = registered_public_key(&created_cred)?;
+
+    let (client_data_json, rebuilt) =
+        options.client_data_json(&rp, &created_cred.response.client_data_json);
+
+    let mut auth_data =
+        parse_attestation_object(&created_cred.response.attestation_object)?.auth_data;
+    let mut patched = options.authenticator.apply(&mut auth_data) || rebuilt;
+    if options.alg != CredentialAlgorithm::Es256 {
+        let key = replace_credential_key(&mut store, options.alg)?;
+        public_key = CredentialPublicKey::new(&key, None)?;
+        let cose_key = public_cose_key(&key).to_vec().unwrap();
+        auth_data = replace_credential_public_key(&auth_data, &cose_key)?;
+        patched = true;
+    }
+    let mut client_extension_results = None;
+    if !options.extensions.is_default() {
+        let mut state = store.extension_state();
+        let outputs = registration_extensions(&options.extensions, &mut state)?;
+        store.set_extension_state(state)?;
+        if let Some(extensions) = outputs.authenticator {
+            auth_data = set_extensions(&auth_data, &extensions)?;
+            patched = true;
+        }
+        client_extension_results = Some(outputs.client.into());
+    }
+    if let Some(sign_count) = options.authenticator.sign_count {
+        store.set_counter(sign_count)?;
+    }
+
+    // passkey-rs only attests with `none`, other formats are signed here.
+    let (attestation_object_bytes, attestation_root) = match (options.attestation, patched) {
+        (AttestationFormat::None, false) => {
+            (created_cred.response.attestation_object.to_vec(), None)
+        }
+        (format, _) => {
+            let key = store
+                .credential_key()
+                .unwrap_or_else(|| store.passkey().unwrap().key);
+            attest(format, &auth_data, client_data_json.as_bytes(), &key)?
+        }
     };
+    let attestation_object = parse_attestation_object(&attestation_object_bytes)?;
+    let authenticator_data = parse_authenticator_data(&attestation_object.auth_data)?;
+
+    Ok(serde_json::to_string_pretty(&PasskeyCredential {
+        credential,
+        credential_id: to_0x_hex(credential_id),
+        rp_id: rp.id,
+        origin: rp.origin,
+        public_key,
+        client_data_json,
+        attestation_object: to_0x_hex(&attestation_object_bytes),
+        fmt: attestation_object.fmt,
+        att_stmt: cbor_to_json(&attestation_object.att_stmt),
+        authenticator_data: authenticator_data.fields(),
+        client_extension_results,
+        attestation_root,
+    })?)
+}
+
+/// Signs `challenge` with the credential saved at `credential`, or with a
+/// credential registered for this challenge only, with a key of
+/// `options.alg`. Without `args_version` the args take the default layout of
+/// the signature's algorithm.
+pub async fn generate_args(
+    challenge: String,
+    credential: Option<String>,
+    options: &PasskeyOptions,
+    args_version: Option<u8>,
+) -> anyhow::Result<String> {
     let base64url_engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
-    let challenge = from_0x_hex(&challenge).unwrap();
+    let challenge = from_0x_hex(&challenge)?;
+    let (mut store, registration) = match &credential {
+        Some(path) => (FileCredentialStore::load(path)?, None),
+        None => (
+            FileCredentialStore::ephemeral(),
+            Some(options.registration(challenge.clone().into())),
+        ),
+    };
+    // A saved credential signs with its own algorithm whatever `options.alg`
+    // says. Checked before signing so a failure doesn't use up a counter.
+    let alg = match &credential {
+        Some(_) => store.algorithm()?,
+        None => options.alg,
+    };
+    if alg != CredentialAlgorithm::Es256 {
+        if options.signature_s != SignatureS::AsSigned {
+            bail!(
+                "only ES256 signatures have a low and high s, not {}",
+                alg.name()
+            );
+        }
+        if let Some(version) = args_version.filter(|v| *v < ARGS_VERSION_ANY_ALGORITHM) {
+            bail!(
+                "args version {} only holds ES256 signatures, not {}",
+                version,
+                alg.name()
+            );
+        }
+    }
     println!("challenge: {}", base64url_engine.encode(&challenge));
+    let stored_rp_id = store.passkey().map(|passkey| passkey.rp_id);
+    let rp = options.relying_party(stored_rp_id.as_deref());
     // Set up a client, create and authenticate a credential, then report results.
     let (created_cred, authed_cred) = client_setup(
-        challenge.into(), // challenge_bytes_from_rp
-        PublicKeyCredentialParameters {
-            ty: PublicKeyCredentialType::PublicKey,
-            alg: iana::Algorithm::ES256,
-        },
-        &rp_url, // origin
-        user_entity.clone(),
+        store.clone(),
+        &rp.id,
+        &rp.client_origin,
+        registration,
+        Some(challenge.into()),
+        &options.authenticator,
     )
     .await
-    .unwrap();
-
-    let client_data_json =
-        String::from_utf8(authed_cred.response.client_data_json.to_vec()).unwrap();
+    .map_err(|e| match store.take_save_error() {
+        Some(error) => anyhow::anyhow!(error),
+        None => anyhow::anyhow!("authentication failed: {:?}", e),
+    })?;
+    let authed_cred = authed_cred.unwrap();
+    let passkey = store.passkey().unwrap();
+    if created_cred.is_some() && alg != CredentialAlgorithm::Es256 {
+        replace_credential_key(&mut store, alg)?;
+    }
 
-    let index_a =
-        index_of_sub_array(client_data_json.as_bytes(), r#"challenge":""#.as_bytes(), 0).unwrap();
-    let index_b =
-        index_of_sub_array(client_data_json.as_bytes(), r#"""#.as_bytes(), index_a + 12).unwrap();
+    let (client_data_json, rebuilt) =
+        options.client_data_json(&rp, &authed_cred.response.client_data_json);
+    let mut authenticator_data = authed_cred.response.authenticator_data.to_vec();
+    let mut modified = options.authenticator.apply(&mut authenticator_data) || rebuilt;
+    let mut client_extension_results = None;
+    if !options.extensions.is_default() {
+        let mut state = store.extension_state();
+        if created_cred.is_some() {
+            registration_extensions(&options.extensions, &mut state)?;
+        }
+        let outputs = authentication_extensions(&options.extensions, &mut state)?;
+        store.set_extension_state(state)?;
+        if let Some(extensions) = outputs.authenticator {
+            authenticator_data = set_extensions(&authenticator_data, &extensions)?;
+            modified = true;
+        }
+        client_extension_results = Some(outputs.client.into());
+    }
+    let signature = match (store.credential_key(), modified) {
+        (Some(key), _) => resign(&key, &authenticator_data, client_data_json.as_bytes())?,
+        (None, true) => resign(
+            &passkey.key,
+            &authenticator_data,
+            client_data_json.as_bytes(),
+        )?,
+        (None, false) => {
+            ecdsa::Signature::from_der(authed_cred.response.signature.as_slice())?.into()
+        }
+    };
+    if let Some(sign_count) = options.authenticator.sign_count {
+        store.set_counter(sign_count)?;
+    }
+    let signature = match signature {
+        AssertionSignature::Es256 { r, s } => {
+            let signature = ecdsa::Signature::from_scalars(r, s)?;
+            adjust_s(signature, options.signature_s).into()
+        }
+        signature => signature,
+    };
 
-    let client_data_json_pre = client_data_json[..index_a + 12].as_bytes().to_vec();
-    let client_data_json_post = client_data_json[index_b..].as_bytes().to_vec();
+    let challenge_value = challenge_range(&client_data_json)?;
+    let client_data_json_pre = client_data_json[..challenge_value.start]
+        .as_bytes()
+        .to_vec();
+    let client_data_json_post = client_data_json[challenge_value.end..].as_bytes().to_vec();
     println!("client_data_json: {}", &client_data_json);
 
-    let signature = ecdsa::Signature::from_der(authed_cred.response.signature.as_slice()).unwrap();
-
-    let pk = created_cred.response.public_key.clone().unwrap();
+    let (credential_id, public_key) = match (created_cred, store.credential_key()) {
+        (_, Some(key)) => (
+            passkey.credential_id.to_vec(),
+            CredentialPublicKey::new(&key, None)?,
+        ),
+        (Some(created_cred), None) => registered_public_key(&created_cred)?,
+        (None, None) => (
+            passkey.credential_id.to_vec(),
+            CredentialPublicKey::new(&passkey.key, None)?,
+        ),
+    };
 
-    let args = [
-        signature.r().to_bytes().as_slice(),
-        signature.s().to_bytes().as_slice(),
-        &(authed_cred.response.authenticator_data.len() as u32).to_be_bytes(),
-        authed_cred.response.authenticator_data.as_slice(),
-        &(client_data_json_pre.len() as u32).to_be_bytes(),
-        &client_data_json_pre,
-        &(client_data_json_post.len() as u32).to_be_bytes(),
-        &client_data_json_post,
-    ]
-    .concat();
+    let args_version = args_version.unwrap_or_else(|| default_args_version(signature.algorithm()));
+    let args = pack_args(
+        &AssertionArgs {
+            signature,
+            authenticator_data: authenticator_data.clone(),
+            client_data_json_pre,
+            client_data_json_post,
+        },
+        args_version,
+    )?;
 
     let passkey_args = PasskeyArgs {
-        q_x: to_0x_hex(&pk.as_slice()[27..59]),
-        q_y: to_0x_hex(&pk.as_slice()[59..91]),
+        public_key,
+        credential_id: to_0x_hex(credential_id),
+        authenticator_data: parse_authenticator_data(&authenticator_data)?.fields(),
+        client_extension_results,
+        args_version,
         args: to_0x_hex(&args),
     };
 
-    serde_json::to_string_pretty(&passkey_args).unwrap()
+    Ok(serde_json::to_string_pretty(&passkey_args)?)
+}
+
+#[tokio::test]
+async fn test_sign_with_saved_algorithm() {
+    let path = |alg: &str| {
+        std::env::temp_dir()
+            .join(format!("passkey-{}-{}.json", alg, rand::random::<u64>()))
+            .to_string_lossy()
+            .to_string()
+    };
+    let (es256, rs256) = (path("es256"), path("rs256"));
+    let options = PasskeyOptions {
+        alg: CredentialAlgorithm::Rs256,
+        ..Default::default()
+    };
+    register_credential(rs256.clone(), false, &options)
+        .await
+        .unwrap();
+    register_credential(es256.clone(), false, &PasskeyOptions::default())
+        .await
+        .unwrap();
+
+    // The saved credential's algorithm counts, not `options.alg`.
+    let sign = |credential: &str, signature_s, args_version| {
+        let options = PasskeyOptions {
+            alg: CredentialAlgorithm::Rs256,
+            signature_s,
+            ..Default::default()
+        };
+        let credential = Some(credential.to_string());
+        async move { generate_args("0x01".to_string(), credential, &options, args_version).await }
+    };
+    assert!(sign(&rs256, SignatureS::AsSigned, Some(1)).await.is_err());
+    assert!(sign(&rs256, SignatureS::Low, None).await.is_err());
+    let signed: PasskeyArgs =
+        serde_json::from_str(&sign(&rs256, SignatureS::AsSigned, None).await.unwrap()).unwrap();
+    assert_eq!(signed.args_version, ARGS_VERSION_ANY_ALGORITHM);
+    let signed: PasskeyArgs =
+        serde_json::from_str(&sign(&es256, SignatureS::Low, Some(1)).await.unwrap()).unwrap();
+    assert_eq!(signed.args_version, 1);
+
+    std::fs::remove_file(&rs256).unwrap();
+    std::fs::remove_file(&es256).unwrap();
+}
+
+#[test]
+fn test_adjust_s() {
+    use ecdsa::signature::Signer;
+
+    let signing_key = ecdsa::SigningKey::random(&mut rand::thread_rng());
+    let signature: ecdsa::Signature = signing_key.sign(b"message");
+    let low = adjust_s(signature, SignatureS::Low);
+    let high = adjust_s(signature, SignatureS::High);
+    assert!(!bool::from(low.s().is_high()));
+    assert!(bool::from(high.s().is_high()));
+    assert_eq!(low.r(), high.r());
+    for signature in [low, high] {
+        assert!(ecdsa::signature::Verifier::verify(
+            signing_key.verifying_key(),
+            b"message",
+            &signature
+        )
+        .is_ok());
+    }
+}
+
+#[tokio::test]
+async fn test_register_and_sign() {
+    let path = std::env::temp_dir()
+        .join(format!("passkey-{}.json", rand::random::<u64>()))
+        .to_string_lossy()
+        .to_string();
+    let options = PasskeyOptions::default();
+    let registered: PasskeyCredential = serde_json::from_str(
+        &register_credential(path.clone(), false, &options)
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(FileCredentialStore::create(&path, false).is_err());
+    let missing_dir = format!("{}.missing/credential.json", path);
+    let error = register_credential(missing_dir, false, &options)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().starts_with("can't save"));
+    assert!(FileCredentialStore::create(&path, true).is_ok());
+
+    let mut sign_counts = vec![];
+    for challenge in ["0x01", "0x02"] {
+        let signed: PasskeyArgs = serde_json::from_str(
+            &generate_args(challenge.to_string(), Some(path.clone()), &options, None)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(signed.credential_id, registered.credential_id);
+        assert_eq!(signed.public_key.q_x, registered.public_key.q_x);
+        assert_eq!(signed.public_key.q_y, registered.public_key.q_y);
+        sign_counts.push(signed.authenticator_data.sign_count);
+    }
+    assert!(sign_counts[0] > registered.authenticator_data.sign_count);
+    assert!(sign_counts[1] > sign_counts[0]);
+
+    // `keys show` of the credential prints the same point.
+    let info = FileCredentialStore::load(&path)
+        .unwrap()
+        .private_key()
+        .unwrap()
+        .info()
+        .unwrap();
+    let q_x = registered.public_key.q_x.unwrap();
+    let q_y = registered.public_key.q_y.unwrap();
+    assert_eq!(info.public_key, format!("0x04{}{}", &q_x[2..], &q_y[2..]));
+    std::fs::remove_file(&path).unwrap();
 }
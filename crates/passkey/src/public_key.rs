@@ -0,0 +1,191 @@
+//! The credential public key, decoded from its COSE_Key or from an SPKI.
+
+use anyhow::{anyhow, bail};
+use coset::{cbor::Value, iana, Algorithm, CborSerializable, CoseKey, KeyType, Label};
+use p256::{
+    ecdsa::SigningKey,
+    elliptic_curve::sec1::ToEncodedPoint,
+    pkcs8::{DecodePublicKey, EncodePublicKey},
+    PublicKey,
+};
+use rsa::{traits::PublicKeyParts, BigUint, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use utils::to_0x_hex;
+
+use crate::algorithm::CredentialAlgorithm;
+
+/// The SubjectPublicKeyInfo of an Ed25519 key up to the key itself
+/// (RFC 8410).
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// The public key in the encodings verifiers take: the coordinates for
+/// ES256, the modulus and exponent for RS256 and the 32 byte key for EdDSA.
+#[derive(Serialize, Deserialize)]
+pub struct CredentialPublicKey {
+    /// `ES256`, `RS256` or `EdDSA`.
+    pub alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q_x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q_y: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ed25519_key: Option<String>,
+    /// The COSE_Key without private parameters.
+    pub cose_key: String,
+    /// The DER SubjectPublicKeyInfo.
+    pub spki: String,
+}
+
+fn param(key: &CoseKey, label: i64) -> Option<&Value> {
+    key.params
+        .iter()
+        .find(|(l, _)| *l == Label::Int(label))
+        .map(|(_, value)| value)
+}
+
+pub(crate) fn bytes_param<'a>(
+    key: &'a CoseKey,
+    label: i64,
+    name: &str,
+) -> anyhow::Result<&'a [u8]> {
+    match param(key, label) {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => bail!("COSE_Key is missing {}", name),
+    }
+}
+
+/// Decodes an ES256 COSE_Key, checking the key type, curve and that the
+/// point is on the curve.
+pub fn p256_public_key(key: &CoseKey) -> anyhow::Result<PublicKey> {
+    if key.kty != KeyType::Assigned(iana::KeyType::EC2) {
+        bail!("not an EC2 key: {:?}", key.kty);
+    }
+    match &key.alg {
+        None | Some(Algorithm::Assigned(iana::Algorithm::ES256)) => {}
+        Some(alg) => bail!("not an ES256 key: {:?}", alg),
+    }
+    let crv = param(key, iana::Ec2KeyParameter::Crv as i64);
+    if crv != Some(&Value::from(iana::EllipticCurve::P_256 as i64)) {
+        bail!("not a P-256 key: {:?}", crv);
+    }
+    let x = bytes_param(key, iana::Ec2KeyParameter::X as i64, "x")?;
+    let y = bytes_param(key, iana::Ec2KeyParameter::Y as i64, "y")?;
+    if x.len() != 32 || y.len() != 32 {
+        bail!("P-256 coordinates must be 32 bytes");
+    }
+    let point = [&[0x04], x, y].concat();
+    PublicKey::from_sec1_bytes(&point).map_err(|_| anyhow!("point is not on P-256"))
+}
+
+/// Decodes an RS256 COSE_Key.
+pub fn rsa_public_key(key: &CoseKey) -> anyhow::Result<RsaPublicKey> {
+    if key.kty != KeyType::Assigned(iana::KeyType::RSA) {
+        bail!("not an RSA key: {:?}", key.kty);
+    }
+    let n = bytes_param(key, iana::RsaKeyParameter::N as i64, "n")?;
+    let e = bytes_param(key, iana::RsaKeyParameter::E as i64, "e")?;
+    Ok(RsaPublicKey::new(
+        BigUint::from_bytes_be(n),
+        BigUint::from_bytes_be(e),
+    )?)
+}
+
+/// Decodes an EdDSA COSE_Key, checking the curve and that the key is a
+/// valid point.
+pub fn ed25519_public_key(key: &CoseKey) -> anyhow::Result<ed25519_dalek::VerifyingKey> {
+    if key.kty != KeyType::Assigned(iana::KeyType::OKP) {
+        bail!("not an OKP key: {:?}", key.kty);
+    }
+    let crv = param(key, iana::OkpKeyParameter::Crv as i64);
+    if crv != Some(&Value::from(iana::EllipticCurve::Ed25519 as i64)) {
+        bail!("not an Ed25519 key: {:?}", crv);
+    }
+    let x = bytes_param(key, iana::OkpKeyParameter::X as i64, "x")?;
+    let x = x
+        .try_into()
+        .map_err(|_| anyhow!("Ed25519 keys are 32 bytes"))?;
+    ed25519_dalek::VerifyingKey::from_bytes(x).map_err(|_| anyhow!("invalid Ed25519 key"))
+}
+
+pub fn p256_public_key_from_spki(der: &[u8]) -> anyhow::Result<PublicKey> {
+    PublicKey::from_public_key_der(der).map_err(|e| anyhow!("invalid P-256 SPKI: {}", e))
+}
+
+/// The private key of a passkey's COSE_Key.
+pub fn p256_signing_key(key: &CoseKey) -> anyhow::Result<SigningKey> {
+    let d = bytes_param(key, iana::Ec2KeyParameter::D as i64, "d")?;
+    SigningKey::from_slice(d).map_err(|e| anyhow!("invalid P-256 private key: {}", e))
+}
+
+/// `key` without its private parameters.
+pub fn public_cose_key(key: &CoseKey) -> CoseKey {
+    let public: &[i64] = match key.kty {
+        KeyType::Assigned(iana::KeyType::RSA) => &[
+            iana::RsaKeyParameter::N as i64,
+            iana::RsaKeyParameter::E as i64,
+        ],
+        // The same labels for OKP keys.
+        _ => &[
+            iana::Ec2KeyParameter::Crv as i64,
+            iana::Ec2KeyParameter::X as i64,
+            iana::Ec2KeyParameter::Y as i64,
+        ],
+    };
+    let mut key = key.clone();
+    key.params.retain(|(label, _)| match label {
+        Label::Int(label) => public.contains(label),
+        Label::Text(_) => true,
+    });
+    key
+}
+
+impl CredentialPublicKey {
+    /// `cose` is the COSE_Key as encoded by the authenticator, if known.
+    pub fn new(key: &CoseKey, cose: Option<Vec<u8>>) -> anyhow::Result<Self> {
+        let cose = match cose {
+            Some(cose) => cose,
+            None => public_cose_key(key)
+                .to_vec()
+                .map_err(|e| anyhow!("can't encode COSE_Key: {:?}", e))?,
+        };
+        let algorithm = CredentialAlgorithm::of_key(key)?;
+        let mut public_key = CredentialPublicKey {
+            alg: algorithm.name().to_string(),
+            q_x: None,
+            q_y: None,
+            n: None,
+            e: None,
+            ed25519_key: None,
+            cose_key: to_0x_hex(cose),
+            spki: String::new(),
+        };
+        match algorithm {
+            CredentialAlgorithm::Es256 => {
+                let p256_key = p256_public_key(key)?;
+                let point = p256_key.to_encoded_point(false);
+                public_key.q_x = Some(to_0x_hex(point.x().unwrap()));
+                public_key.q_y = Some(to_0x_hex(point.y().unwrap()));
+                public_key.spki = to_0x_hex(p256_key.to_public_key_der()?.as_bytes());
+            }
+            CredentialAlgorithm::Rs256 => {
+                let rsa_key = rsa_public_key(key)?;
+                public_key.n = Some(to_0x_hex(rsa_key.n().to_bytes_be()));
+                public_key.e = Some(to_0x_hex(rsa_key.e().to_bytes_be()));
+                public_key.spki = to_0x_hex(rsa_key.to_public_key_der()?.as_bytes());
+            }
+            CredentialAlgorithm::EdDsa => {
+                let ed25519_key = ed25519_public_key(key)?;
+                public_key.ed25519_key = Some(to_0x_hex(ed25519_key.to_bytes()));
+                public_key.spki =
+                    to_0x_hex([&ED25519_SPKI_PREFIX[..], &ed25519_key.as_bytes()[..]].concat());
+            }
+        }
+        Ok(public_key)
+    }
+}
@@ -0,0 +1,287 @@
+//! A `CredentialStore` backed by a JSON file, so one passkey can be
+//! registered once and sign many challenges.
+
+use anyhow::bail;
+use coset::{CborSerializable, CoseKey};
+use passkey::{
+    authenticator::CredentialStore,
+    types::{
+        ctap2::{
+            make_credential::{PublicKeyCredentialRpEntity, PublicKeyCredentialUserEntity},
+            Ctap2Error, StatusCode,
+        },
+        webauthn::PublicKeyCredentialDescriptor,
+        Passkey,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use utils::{from_0x_hex, keys::PrivateKey, keystore::write_private_file, to_0x_hex};
+
+use crate::{
+    algorithm::{rsa_private_key, CredentialAlgorithm},
+    public_key::p256_signing_key,
+};
+
+/// The credential file. `key` is the COSE_Key with the private key.
+#[derive(Serialize, Deserialize)]
+struct StoredPasskey {
+    credential_id: String,
+    rp_id: String,
+    user_handle: Option<String>,
+    counter: Option<u32>,
+    key: String,
+    /// The RS256 or EdDSA COSE_Key that replaces `key` in what the relying
+    /// party sees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credential_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prf_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    large_blob: Option<String>,
+}
+
+/// What the extensions keep per credential.
+#[derive(Clone, Debug, Default)]
+pub struct ExtensionState {
+    /// The CredRandom of hmac-secret, which the PRF outputs derive from.
+    pub prf_secret: Option<Vec<u8>>,
+    pub large_blob: Option<Vec<u8>>,
+}
+
+fn decode_hex(hex: Option<String>) -> anyhow::Result<Option<Vec<u8>>> {
+    hex.map(|hex| from_0x_hex(&hex)).transpose()
+}
+
+fn decode_cose_key(hex: &str) -> anyhow::Result<CoseKey> {
+    CoseKey::from_slice(&from_0x_hex(hex)?)
+        .map_err(|e| anyhow::anyhow!("invalid credential key: {:?}", e))
+}
+
+fn encode_cose_key(key: &CoseKey) -> Result<String, StatusCode> {
+    Ok(to_0x_hex(
+        key.clone().to_vec().map_err(|_| Ctap2Error::Other)?,
+    ))
+}
+
+/// Holds at most one passkey. Without a path the passkey only lives as long
+/// as the store, like the `Option<Passkey>` store of passkey-rs. Clones share
+/// the passkey, so a clone kept outside the authenticator sees its updates.
+#[derive(Clone)]
+pub struct FileCredentialStore {
+    path: Option<String>,
+    /// Whether saving may replace an existing file.
+    force: bool,
+    passkey: Arc<Mutex<Option<Passkey>>>,
+    credential_key: Arc<Mutex<Option<CoseKey>>>,
+    extension_state: Arc<Mutex<ExtensionState>>,
+    /// Why the last save failed. The `CredentialStore` methods only return
+    /// a CTAP status, which can't say that.
+    save_error: Arc<Mutex<Option<String>>>,
+}
+
+impl FileCredentialStore {
+    pub fn ephemeral() -> Self {
+        FileCredentialStore {
+            path: None,
+            force: false,
+            passkey: Default::default(),
+            credential_key: Default::default(),
+            extension_state: Default::default(),
+            save_error: Default::default(),
+        }
+    }
+
+    /// An empty store that saves the credential it registers to `path`.
+    pub fn create(path: &str, force: bool) -> anyhow::Result<Self> {
+        if !force && std::path::Path::new(path).exists() {
+            bail!("{} already exists, pass --force to overwrite it", path);
+        }
+        Ok(FileCredentialStore {
+            path: Some(path.to_string()),
+            force,
+            passkey: Default::default(),
+            credential_key: Default::default(),
+            extension_state: Default::default(),
+            save_error: Default::default(),
+        })
+    }
+
+    /// Loads the credential saved at `path`.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let stored: StoredPasskey = serde_json::from_slice(&std::fs::read(path)?)?;
+        let passkey = Passkey {
+            key: decode_cose_key(&stored.key)?,
+            credential_id: from_0x_hex(&stored.credential_id)?.into(),
+            rp_id: stored.rp_id,
+            user_handle: match stored.user_handle {
+                Some(handle) => Some(from_0x_hex(&handle)?.into()),
+                None => None,
+            },
+            counter: stored.counter,
+        };
+        Ok(FileCredentialStore {
+            path: Some(path.to_string()),
+            force: true,
+            passkey: Arc::new(Mutex::new(Some(passkey))),
+            credential_key: Arc::new(Mutex::new(match stored.credential_key {
+                Some(key) => Some(decode_cose_key(&key)?),
+                None => None,
+            })),
+            extension_state: Arc::new(Mutex::new(ExtensionState {
+                prf_secret: decode_hex(stored.prf_secret)?,
+                large_blob: decode_hex(stored.large_blob)?,
+            })),
+            save_error: Default::default(),
+        })
+    }
+
+    pub fn passkey(&self) -> Option<Passkey> {
+        self.passkey.lock().unwrap().clone()
+    }
+
+    pub fn credential_key(&self) -> Option<CoseKey> {
+        self.credential_key.lock().unwrap().clone()
+    }
+
+    /// The algorithm the credential signs with, ES256 unless it has a
+    /// credential key.
+    pub fn algorithm(&self) -> anyhow::Result<CredentialAlgorithm> {
+        match self.credential_key() {
+            Some(key) => CredentialAlgorithm::of_key(&key),
+            None => Ok(CredentialAlgorithm::Es256),
+        }
+    }
+
+    /// Takes the error of the last failed save.
+    pub fn take_save_error(&self) -> Option<String> {
+        self.save_error.lock().unwrap().take()
+    }
+
+    /// The key the credential signs with, for the `keys` command. Ed25519
+    /// keys aren't among its key types.
+    pub fn private_key(&self) -> anyhow::Result<PrivateKey> {
+        let passkey = self
+            .passkey()
+            .ok_or_else(|| anyhow::anyhow!("no credential"))?;
+        let key = self.credential_key().unwrap_or(passkey.key);
+        match CredentialAlgorithm::of_key(&key)? {
+            CredentialAlgorithm::Es256 => Ok(PrivateKey::P256(p256::SecretKey::from_slice(
+                &p256_signing_key(&key)?.to_bytes(),
+            )?)),
+            CredentialAlgorithm::Rs256 => Ok(PrivateKey::Rsa(rsa_private_key(&key)?)),
+            CredentialAlgorithm::EdDsa => {
+                bail!("EdDSA credential keys can't be shown or exported")
+            }
+        }
+    }
+
+    /// Saves the key that signs instead of the authenticator's.
+    pub fn set_credential_key(&mut self, key: CoseKey) -> anyhow::Result<()> {
+        let passkey = self
+            .passkey()
+            .ok_or_else(|| anyhow::anyhow!("no credential"))?;
+        *self.credential_key.lock().unwrap() = Some(key);
+        self.force = true;
+        self.save_with_error(passkey)
+    }
+
+    pub fn extension_state(&self) -> ExtensionState {
+        self.extension_state.lock().unwrap().clone()
+    }
+
+    pub fn set_extension_state(&mut self, state: ExtensionState) -> anyhow::Result<()> {
+        let passkey = self
+            .passkey()
+            .ok_or_else(|| anyhow::anyhow!("no credential"))?;
+        *self.extension_state.lock().unwrap() = state;
+        self.force = true;
+        self.save_with_error(passkey)
+    }
+
+    /// Sets the counter the next assertion continues from.
+    pub fn set_counter(&mut self, counter: u32) -> anyhow::Result<()> {
+        let mut passkey = self
+            .passkey()
+            .ok_or_else(|| anyhow::anyhow!("no credential"))?;
+        passkey.counter = Some(counter);
+        // The credential was saved already, this updates it.
+        self.force = true;
+        self.save_with_error(passkey)
+    }
+
+    fn save_with_error(&mut self, passkey: Passkey) -> anyhow::Result<()> {
+        self.save(passkey)
+            .map_err(|e| match self.take_save_error() {
+                Some(error) => anyhow::anyhow!(error),
+                None => anyhow::anyhow!("can't save the credential: {:?}", e),
+            })
+    }
+
+    fn save(&mut self, passkey: Passkey) -> Result<(), StatusCode> {
+        if let Some(path) = &self.path {
+            let extension_state = self.extension_state();
+            let stored = StoredPasskey {
+                credential_id: to_0x_hex(passkey.credential_id.as_slice()),
+                rp_id: passkey.rp_id.clone(),
+                user_handle: passkey
+                    .user_handle
+                    .as_ref()
+                    .map(|handle| to_0x_hex(handle.as_slice())),
+                counter: passkey.counter,
+                key: encode_cose_key(&passkey.key)?,
+                credential_key: match self.credential_key() {
+                    Some(key) => Some(encode_cose_key(&key)?),
+                    None => None,
+                },
+                prf_secret: extension_state.prf_secret.as_ref().map(to_0x_hex),
+                large_blob: extension_state.large_blob.as_ref().map(to_0x_hex),
+            };
+            let json = serde_json::to_string_pretty(&stored).unwrap();
+            if let Err(e) = write_private_file(path, json.as_bytes(), self.force) {
+                *self.save_error.lock().unwrap() = Some(format!("can't save {}: {}", path, e));
+                return Err(Ctap2Error::KeyStoreFull.into());
+            }
+            self.force = true;
+        }
+        *self.passkey.lock().unwrap() = Some(passkey);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for FileCredentialStore {
+    type PasskeyItem = Passkey;
+
+    async fn find_credentials(
+        &self,
+        ids: Option<&[PublicKeyCredentialDescriptor]>,
+        rp_id: &str,
+    ) -> Result<Vec<Self::PasskeyItem>, StatusCode> {
+        Ok(self
+            .passkey()
+            .into_iter()
+            .filter(|passkey| passkey.rp_id == rp_id)
+            .filter(|passkey| {
+                ids.map_or(true, |ids| {
+                    ids.iter().any(|id| id.id == passkey.credential_id)
+                })
+            })
+            .collect())
+    }
+
+    async fn save_credential(
+        &mut self,
+        mut cred: Passkey,
+        _user: PublicKeyCredentialUserEntity,
+        _rp: PublicKeyCredentialRpEntity,
+    ) -> Result<(), StatusCode> {
+        // Count signatures from the start so they can be told apart.
+        cred.counter.get_or_insert(0);
+        self.save(cred)
+    }
+
+    async fn update_credential(&mut self, cred: Passkey) -> Result<(), StatusCode> {
+        self.save(cred)
+    }
+}
@@ -0,0 +1,113 @@
+//! The common frame of the contract args.
+//!
+//! Tagged args start with two bytes, the guardian type and the layout
+//! version, followed by big-endian `u32` integers and byte strings prefixed
+//! with their `u32` length. Version 0 is the untagged layout each generator
+//! emitted before the tag existed, see the `args` module of each crate.
+
+use anyhow::{anyhow, bail};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardianType {
+    /// The tag the untagged email layout already started with.
+    Email = 0,
+    OpenID = 1,
+    Passkey = 2,
+}
+
+impl TryFrom<u8> for GuardianType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(GuardianType::Email),
+            1 => Ok(GuardianType::OpenID),
+            2 => Ok(GuardianType::Passkey),
+            _ => bail!("unknown guardian type: {}", value),
+        }
+    }
+}
+
+pub fn args_tag(guardian_type: GuardianType, version: u8) -> [u8; 2] {
+    [guardian_type as u8, version]
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend((value as u32).to_be_bytes());
+}
+
+pub fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len());
+    out.extend(bytes);
+}
+
+/// Reads args front to back, failing on truncated input.
+pub struct ArgsReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ArgsReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ArgsReader { data, offset: 0 }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub fn peek_u8(&self) -> anyhow::Result<u8> {
+        self.data
+            .get(self.offset)
+            .copied()
+            .ok_or_else(|| anyhow!("args truncated at {}", self.offset))
+    }
+
+    pub fn read_fixed(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.remaining() < len {
+            bail!(
+                "args truncated at {}: need {} bytes, {} left",
+                self.offset,
+                len,
+                self.remaining()
+            );
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.read_fixed(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> anyhow::Result<usize> {
+        let bytes = self.read_fixed(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    pub fn read_bytes(&mut self) -> anyhow::Result<Vec<u8>> {
+        let len = self.read_u32()?;
+        Ok(self.read_fixed(len)?.to_vec())
+    }
+
+    /// Reads the tag and returns the version.
+    pub fn read_tag(&mut self, guardian_type: GuardianType) -> anyhow::Result<u8> {
+        let found = GuardianType::try_from(self.read_u8()?)?;
+        if found != guardian_type {
+            bail!("expected {:?} args, found {:?}", guardian_type, found);
+        }
+        self.read_u8()
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        if self.remaining() != 0 {
+            bail!("{} trailing bytes in args", self.remaining());
+        }
+        Ok(())
+    }
+}
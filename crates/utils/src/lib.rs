@@ -1,3 +1,7 @@
+pub mod args;
+pub mod keys;
+pub mod keystore;
+
 pub fn to_0x_hex<T>(data: T) -> String
 where
     T: AsRef<[u8]>,
@@ -9,4 +13,4 @@ where
 
 pub fn from_0x_hex(input: &str) -> anyhow::Result<Vec<u8>> {
     Ok(hex::decode(input.trim_start_matches("0x"))?)
-}
\ No newline at end of file
+}
@@ -1,4 +1,21 @@
-use clap::{Parser, Subcommand};
+use ::utils::{
+    keys::{ExportFormat, KeyType, PrivateKey},
+    keystore::{read_password_file, write_private_file, KeyFile},
+};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use email_gen::{
+    circuit::{CircuitOptions, CircuitTarget},
+    line_endings::LineEndingMode,
+    mock::{ArcOptions, BodyLength, DkimCanonicalization, EmailOptions, SubjectEncoding},
+    tamper::TamperKind,
+    types::DkimParams,
+};
+use passkey_gen::{
+    algorithm::CredentialAlgorithm, attestation::AttestationFormat,
+    authenticator_data::AuthenticatorOptions, client_data::ClientDataOptions,
+    extensions::ExtensionOptions, store::FileCredentialStore, PasskeyOptions, SignatureS,
+};
+use rsa::RsaPublicKey;
 
 pub mod utils;
 
@@ -9,6 +26,222 @@ struct Cli {
     pub command: Commands,
 }
 
+#[derive(Args, Debug, Clone)]
+struct MockEmailArgs {
+    #[arg(long, default_value = "false")]
+    create: bool,
+    #[arg(long, default_value = "email.sk")]
+    sk_path: String,
+    /// Overwrite an existing key with `--create`.
+    #[arg(long, default_value = "false")]
+    force: bool,
+    /// Encrypt the key with the password in this file.
+    #[arg(long)]
+    password_file: Option<String>,
+    /// Size of the key created with `--create`.
+    #[arg(long, default_value = "2048", value_parser = ::utils::keys::parse_rsa_bits)]
+    rsa_bits: usize,
+    #[arg(long, default_value = "Alice <alice@test.com>")]
+    from: String,
+    #[arg(long, default_value = "Bob <bob@test.com>")]
+    to: String,
+    #[arg(long, default_value = "test_subject")]
+    subject: String,
+    #[arg(long, default_value = "test_body")]
+    body: String,
+    #[arg(long, default_value = "test_selector")]
+    selector: String,
+    #[arg(long, default_value = "test_domain")]
+    domain: String,
+    #[arg(long)]
+    cc: Option<String>,
+    #[arg(long)]
+    reply_to: Option<String>,
+    #[arg(long, default_value = "relaxed/relaxed", value_parser = email_gen::mock::parse_canonicalization)]
+    canonicalization: DkimCanonicalization,
+    #[arg(long, value_delimiter = ',', default_value = "From,Subject,To,Date")]
+    signed_headers: Vec<String>,
+    #[arg(long, default_value = "auto", value_parser = email_gen::mock::parse_subject_encoding)]
+    subject_encoding: SubjectEncoding,
+    #[arg(long)]
+    subject_fold_width: Option<usize>,
+    /// Add an `l=` tag, `full` or a byte count.
+    #[arg(long, value_parser = email_gen::mock::parse_body_length)]
+    body_length: Option<BodyLength>,
+    /// Place this recovery hash in the body and append the body section to
+    /// the args.
+    #[arg(long)]
+    body_recovery_hash: Option<String>,
+    /// Forward the email through a list at this domain that adds an ARC set.
+    #[arg(long)]
+    arc_domain: Option<String>,
+    #[arg(long, default_value = "arc")]
+    arc_selector: String,
+    #[arg(long)]
+    arc_subject_prefix: Option<String>,
+    #[arg(long)]
+    arc_footer: Option<String>,
+}
+
+impl MockEmailArgs {
+    fn key_file(&self) -> KeyFile {
+        key_file(
+            &self.sk_path,
+            self.create,
+            self.force,
+            self.password_file.as_deref(),
+        )
+    }
+
+    fn options(&self) -> EmailOptions {
+        EmailOptions {
+            cc: self.cc.clone(),
+            reply_to: self.reply_to.clone(),
+            canonicalization: self.canonicalization,
+            signed_headers: self.signed_headers.clone(),
+            subject_encoding: self.subject_encoding,
+            subject_fold_width: self.subject_fold_width,
+            body_length: self.body_length,
+            arc: self.arc_domain.clone().map(|domain| ArcOptions {
+                selector: self.arc_selector.clone(),
+                domain,
+                subject_prefix: self.arc_subject_prefix.clone(),
+                footer: self.arc_footer.clone(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn generate_params(self) -> (DkimParams, RsaPublicKey) {
+        let options = self.options();
+        let body = match self.body_recovery_hash {
+            Some(hash) => format!("{}\r\n{}", self.body, hash),
+            None => self.body,
+        };
+        email_gen::generate_params(
+            &self.key_file(),
+            self.rsa_bits,
+            self.from,
+            self.to,
+            self.subject,
+            body,
+            self.selector,
+            self.domain,
+            &options,
+        )
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+struct MockPasskeyArgs {
+    /// Defaults to the rp id of the saved credential, then to the host of
+    /// `--origin`.
+    #[arg(long)]
+    rp_id: Option<String>,
+    /// A web origin or an `android:apk-key-hash:` origin.
+    #[arg(long)]
+    origin: Option<String>,
+    #[arg(long, default_value = "passkey@example.org")]
+    user_name: String,
+    #[arg(long, default_value = "Passkey Tester")]
+    user_display_name: String,
+    /// Random unless set.
+    #[arg(long)]
+    user_id: Option<String>,
+    /// `es256`, `rs256` or `eddsa`, the algorithm of registered passkeys.
+    #[arg(long, default_value = "es256", value_parser = passkey_gen::algorithm::parse_credential_algorithm)]
+    alg: CredentialAlgorithm,
+    /// Clear the UP flag.
+    #[arg(long, default_value = "false")]
+    no_user_presence: bool,
+    /// Clear the UV flag.
+    #[arg(long, default_value = "false")]
+    no_user_verification: bool,
+    /// Set the BE flag.
+    #[arg(long, default_value = "false")]
+    backup_eligible: bool,
+    /// Set the BS flag.
+    #[arg(long, default_value = "false")]
+    backup_state: bool,
+    /// Use this sign counter instead of the authenticator's, e.g. 0 or a
+    /// rollback.
+    #[arg(long)]
+    sign_count: Option<u32>,
+    /// Set `crossOrigin` to true in the clientDataJSON.
+    #[arg(long, default_value = "false")]
+    cross_origin: bool,
+    /// Add `topOrigin` to the clientDataJSON.
+    #[arg(long)]
+    top_origin: Option<String>,
+    /// Add Chrome's `other_keys_can_be_added_here` member to the
+    /// clientDataJSON.
+    #[arg(long, default_value = "false")]
+    chrome_extra_key: bool,
+    /// Add a `key=value` member to the clientDataJSON. Values that aren't
+    /// JSON are strings.
+    #[arg(long, value_parser = passkey_gen::client_data::parse_extra_field)]
+    extra_field: Vec<(String, serde_json::Value)>,
+    /// Comma separated clientDataJSON keys to put first, e.g.
+    /// `challenge,origin,type`.
+    #[arg(long, value_delimiter = ',')]
+    key_order: Vec<String>,
+    /// Request credProps when registering.
+    #[arg(long, default_value = "false")]
+    cred_props: bool,
+    /// Register the passkey with PRF support.
+    #[arg(long, default_value = "false")]
+    prf: bool,
+    /// Comma separated hex PRF inputs, `first` and optionally `second`.
+    #[arg(long, value_delimiter = ',', value_parser = ::utils::from_0x_hex)]
+    prf_input: Vec<Vec<u8>>,
+    /// Request largeBlob support when registering, read the blob when
+    /// signing.
+    #[arg(long, default_value = "false")]
+    large_blob: bool,
+    /// Write this hex blob when signing.
+    // Spelled out because clap takes a bare `Option<Vec<u8>>` for a list.
+    #[arg(long, value_parser = ::utils::from_0x_hex)]
+    large_blob_write: Option<::std::vec::Vec<u8>>,
+}
+
+impl MockPasskeyArgs {
+    fn options(&self) -> PasskeyOptions {
+        PasskeyOptions {
+            rp_id: self.rp_id.clone(),
+            origin: self.origin.clone(),
+            user_name: self.user_name.clone(),
+            user_display_name: self.user_display_name.clone(),
+            user_id: self
+                .user_id
+                .as_ref()
+                .map(|id| utils::from_0x_hex(id).unwrap()),
+            alg: self.alg,
+            authenticator: AuthenticatorOptions {
+                user_present: !self.no_user_presence,
+                user_verified: !self.no_user_verification,
+                backup_eligible: self.backup_eligible,
+                backup_state: self.backup_state,
+                sign_count: self.sign_count,
+            },
+            client_data: ClientDataOptions {
+                cross_origin: self.cross_origin,
+                top_origin: self.top_origin.clone(),
+                chrome_extra_key: self.chrome_extra_key,
+                extra_fields: self.extra_field.clone(),
+                key_order: self.key_order.clone(),
+            },
+            extensions: ExtensionOptions {
+                cred_props: self.cred_props,
+                prf: self.prf,
+                prf_inputs: self.prf_input.clone(),
+                large_blob: self.large_blob,
+                large_blob_write: self.large_blob_write.clone(),
+            },
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Subcommand, Clone)]
 enum Commands {
     OpenID {
@@ -16,6 +249,15 @@ enum Commands {
         create: bool,
         #[arg(long, default_value = "openid.sk")]
         sk_path: String,
+        /// Overwrite an existing key with `--create`.
+        #[arg(long, default_value = "false")]
+        force: bool,
+        /// Encrypt the key with the password in this file.
+        #[arg(long)]
+        password_file: Option<String>,
+        /// Size of the key created with `--create`.
+        #[arg(long, default_value = "2048", value_parser = openid_gen::parse_rsa_bits)]
+        rsa_bits: usize,
         #[arg(long, default_value = "default_kid")]
         kid: String,
         #[arg(long, default_value = "default_iss")]
@@ -26,64 +268,486 @@ enum Commands {
         aud: String,
         #[arg(long, default_value = "default_nonce")]
         nonce: String,
+        #[arg(long, default_value_t = openid_gen::args::ARGS_VERSION)]
+        args_version: u8,
     },
     Email {
+        #[command(flatten)]
+        email: MockEmailArgs,
+        #[arg(long, default_value_t = email_gen::args::ARGS_VERSION)]
+        args_version: u8,
+        /// Print a tampered email of this kind with its expected verdict
+        /// instead of the args.
+        #[arg(long, value_parser = email_gen::tamper::parse_tamper_kind)]
+        tamper: Option<TamperKind>,
+    },
+    EmailCircuit {
+        #[command(flatten)]
+        email: MockEmailArgs,
+        #[arg(long, default_value = "circom", value_parser = email_gen::circuit::parse_circuit_target)]
+        target: CircuitTarget,
+        #[arg(long, default_value = "1024")]
+        max_header_len: usize,
+        #[arg(long)]
+        limb_bits: Option<usize>,
+        #[arg(long)]
+        limb_count: Option<usize>,
+        #[arg(long)]
+        sha_precompute_selector: Option<String>,
+        #[arg(long)]
+        pepper: Option<String>,
+    },
+    /// Mock a guardian replying to the wallet's recovery request. `--subject`
+    /// is the request subject the recovery hash is appended to, `--body` the
+    /// reply text above the quoted request.
+    EmailReply {
+        #[command(flatten)]
+        email: MockEmailArgs,
+        #[arg(long)]
+        recovery_hash: String,
+        #[arg(long, default_value_t = email_gen::args::ARGS_VERSION)]
+        args_version: u8,
+    },
+    /// Parse a raw email, e.g. an `.eml` export, into its DKIM params.
+    EmailParse {
+        #[arg(long)]
+        eml: String,
+        #[arg(long, default_value = "normalize", value_parser = email_gen::line_endings::parse_line_ending_mode)]
+        line_endings: LineEndingMode,
+    },
+    EmailCommitment {
+        #[arg(long)]
+        address: String,
+        #[arg(long)]
+        pepper: Option<String>,
         #[arg(long, default_value = "false")]
-        create: bool,
-        #[arg(long, default_value = "email.sk")]
-        sk_path: String,
-        #[arg(long, default_value = "Alice <alice@test.com>")]
-        from: String,
-        #[arg(long, default_value = "Bob <bob@test.com>")]
-        to: String,
-        #[arg(long, default_value = "test_subject")]
-        subject: String,
-        #[arg(long, default_value = "test_body")]
-        body: String,
-        #[arg(long, default_value = "test_selector")]
-        selector: String,
-        #[arg(long, default_value = "test_domain")]
-        domain: String,
+        fold_gmail: bool,
+    },
+    /// Manage the RSA keys of the email and OpenID generators and P-256
+    /// passkey keys.
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
     },
     Passkey {
+        #[command(subcommand)]
+        command: PasskeyCommand,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum PasskeyCommand {
+    /// Register a passkey and save it to `--credential`.
+    Register {
+        #[arg(long)]
+        credential: String,
+        #[arg(long, default_value = "false")]
+        force: bool,
+        #[command(flatten)]
+        passkey: MockPasskeyArgs,
+        /// `none`, `packed-self`, `packed` or `fido-u2f`.
+        #[arg(long, default_value = "none", value_parser = passkey_gen::attestation::parse_attestation_format)]
+        attestation: AttestationFormat,
+    },
+    /// Sign `--challenge` with the passkey saved at `--credential`, or with a
+    /// passkey registered for this challenge only.
+    Sign {
         #[arg(long)]
         challenge: String,
+        #[arg(long)]
+        credential: Option<String>,
+        #[command(flatten)]
+        passkey: MockPasskeyArgs,
+        /// Emit the low-S form of ES256 signatures.
+        #[arg(long, default_value = "false", conflicts_with = "force_high_s")]
+        normalize_s: bool,
+        /// Emit the high-S form of ES256 signatures.
+        #[arg(long, default_value = "false")]
+        force_high_s: bool,
+        /// Defaults to 1 for ES256 signatures and 2 for RS256 and EdDSA
+        /// ones, which earlier layouts can't hold.
+        #[arg(long)]
+        args_version: Option<u8>,
     },
 }
 
+impl PasskeyCommand {
+    /// Rejects the combinations that depend on the values of `--alg`, the
+    /// algorithm of `--credential` and the extension options.
+    fn validate(&self) -> Result<(), clap::Error> {
+        let error = |kind, message: String| Err(Cli::command().error(kind, message));
+        let (PasskeyCommand::Register { passkey, .. } | PasskeyCommand::Sign { passkey, .. }) =
+            self;
+        if passkey.prf_input.len() > 2 {
+            return error(
+                ErrorKind::TooManyValues,
+                format!(
+                    "--prf-input takes at most two inputs, got {}",
+                    passkey.prf_input.len()
+                ),
+            );
+        }
+        match self {
+            PasskeyCommand::Register { attestation, .. } => {
+                if !passkey.prf_input.is_empty() || passkey.large_blob_write.is_some() {
+                    return error(
+                        ErrorKind::ArgumentConflict,
+                        "--prf-input and --large-blob-write only apply when signing".to_string(),
+                    );
+                }
+                if passkey.alg != CredentialAlgorithm::Es256
+                    && *attestation == AttestationFormat::FidoU2f
+                {
+                    return error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "--attestation fido-u2f only attests ES256 passkeys, not {}",
+                            passkey.alg.name()
+                        ),
+                    );
+                }
+            }
+            PasskeyCommand::Sign {
+                credential,
+                normalize_s,
+                force_high_s,
+                args_version,
+                ..
+            } => {
+                if passkey.prf && passkey.prf_input.is_empty() {
+                    return error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--prf needs a --prf-input to evaluate when signing".to_string(),
+                    );
+                }
+                // A saved credential signs with its own algorithm whatever
+                // `--alg` says. Signing reports a credential that can't be
+                // loaded.
+                let alg = match credential {
+                    Some(path) => {
+                        match FileCredentialStore::load(path).and_then(|store| store.algorithm()) {
+                            Ok(alg) => alg,
+                            Err(_) => return Ok(()),
+                        }
+                    }
+                    None => passkey.alg,
+                };
+                if alg == CredentialAlgorithm::Es256 {
+                    return Ok(());
+                }
+                if *normalize_s || *force_high_s {
+                    return error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "--normalize-s and --force-high-s only apply to ES256 signatures, not {}",
+                            alg.name()
+                        ),
+                    );
+                }
+                if let Some(version) =
+                    args_version.filter(|v| *v < passkey_gen::args::ARGS_VERSION_ANY_ALGORITHM)
+                {
+                    return error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "--args-version {} only holds ES256 signatures, not {}",
+                            version,
+                            alg.name()
+                        ),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum KeysCommand {
+    Generate {
+        #[arg(long)]
+        sk_path: String,
+        #[arg(long, default_value = "rsa", value_parser = ::utils::keys::parse_key_type)]
+        key_type: KeyType,
+        #[arg(long, default_value = "2048", value_parser = ::utils::keys::parse_rsa_bits)]
+        rsa_bits: usize,
+        #[arg(long, default_value = "false")]
+        force: bool,
+        #[arg(long)]
+        password_file: Option<String>,
+    },
+    /// Print the fingerprint, kid and public key encodings.
+    Show {
+        #[arg(long)]
+        sk_path: String,
+        #[arg(long)]
+        password_file: Option<String>,
+    },
+    /// Store a PKCS#1, PKCS#8, SEC1 or JWK private key as PKCS#8.
+    Import {
+        #[arg(long)]
+        input: String,
+        #[arg(long)]
+        sk_path: String,
+        #[arg(long, default_value = "false")]
+        force: bool,
+        #[arg(long)]
+        password_file: Option<String>,
+    },
+    /// Print the private key, or write it to `--out`.
+    Export {
+        #[arg(long)]
+        sk_path: String,
+        #[arg(long)]
+        password_file: Option<String>,
+        #[arg(long, default_value = "pkcs8", value_parser = ::utils::keys::parse_export_format)]
+        format: ExportFormat,
+        #[arg(long)]
+        out: Option<String>,
+        #[arg(long, default_value = "false")]
+        force: bool,
+    },
+}
+
+fn keys_command(command: KeysCommand) -> anyhow::Result<()> {
+    match command {
+        KeysCommand::Generate {
+            sk_path,
+            key_type,
+            rsa_bits,
+            force,
+            password_file,
+        } => {
+            let key = PrivateKey::generate(key_type, rsa_bits)?;
+            key.store(&key_file(&sk_path, true, force, password_file.as_deref()))?;
+            println!("{}", serde_json::to_string_pretty(&key.info()?)?);
+        }
+        KeysCommand::Show {
+            sk_path,
+            password_file,
+        } => {
+            let key = load_key(&sk_path, password_file.as_deref())?;
+            println!("{}", serde_json::to_string_pretty(&key.info()?)?);
+        }
+        KeysCommand::Import {
+            input,
+            sk_path,
+            force,
+            password_file,
+        } => {
+            let key = PrivateKey::import(&std::fs::read_to_string(input)?)?;
+            key.store(&key_file(&sk_path, true, force, password_file.as_deref()))?;
+            println!("{}", serde_json::to_string_pretty(&key.info()?)?);
+        }
+        KeysCommand::Export {
+            sk_path,
+            password_file,
+            format,
+            out,
+            force,
+        } => {
+            let key = load_key(&sk_path, password_file.as_deref())?;
+            let exported = key.export(format)?;
+            match out {
+                Some(out) => write_private_file(&out, exported.as_bytes(), force)?,
+                None => println!("{}", exported.trim_end()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads a key file, or the key of a credential saved by `passkey register`.
+fn load_key(sk_path: &str, password_file: Option<&str>) -> anyhow::Result<PrivateKey> {
+    match FileCredentialStore::load(sk_path) {
+        Ok(store) => store.private_key(),
+        Err(_) => PrivateKey::load(&key_file(sk_path, false, false, password_file)),
+    }
+}
+
+/// Returns the value of `result`, or prints its error and exits.
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1)
+    })
+}
+
+fn key_file(sk_path: &str, create: bool, force: bool, password_file: Option<&str>) -> KeyFile {
+    KeyFile {
+        force,
+        password: password_file.map(|path| read_password_file(path).unwrap()),
+        ..KeyFile::new(sk_path.to_string(), create)
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    if let Commands::Passkey { command } = &cli.command {
+        if let Err(e) = command.validate() {
+            e.exit();
+        }
+    }
     match cli.command {
         Commands::OpenID {
             create,
             sk_path,
+            force,
+            password_file,
+            rsa_bits,
             kid,
             iss,
             sub,
             aud,
             nonce,
+            args_version,
         } => {
-            let res = openid_gen::generate_args(create, sk_path, kid, iss, sub, aud, nonce);
+            let key_file = key_file(&sk_path, create, force, password_file.as_deref());
+            let res = openid_gen::generate_args(
+                &key_file,
+                rsa_bits,
+                kid,
+                iss,
+                sub,
+                aud,
+                nonce,
+                args_version,
+            );
             println!("{}", res);
         }
         Commands::Email {
-            create,
-            sk_path,
-            from,
-            to,
-            subject,
-            body,
-            selector,
-            domain,
+            email,
+            args_version,
+            tamper: Some(kind),
         } => {
-            let res = email_gen::generate_args(
-                create, sk_path, from, to, subject, body, selector, domain,
+            let options = email.options();
+            let res = email_gen::generate_tamper_fixture(
+                &email.key_file(),
+                email.rsa_bits,
+                email.from,
+                email.to,
+                email.subject,
+                email.body,
+                email.selector,
+                email.domain,
+                options,
+                kind,
+                args_version,
             );
             println!("{}", res);
         }
-        Commands::Passkey { challenge } => {
-            let res = passkey_gen::generate_args(challenge).await;
+        Commands::Email {
+            email,
+            args_version,
+            tamper: None,
+        } => {
+            let from = email.from.clone();
+            let body_recovery_hash = email.body_recovery_hash.clone();
+            let (params, public_key) = email.generate_params();
+            let res = or_exit(email_gen::encode_args(
+                params,
+                &public_key,
+                from,
+                body_recovery_hash,
+                args_version,
+            ));
+            println!("{}", res);
+        }
+        Commands::EmailCircuit {
+            email,
+            target,
+            max_header_len,
+            limb_bits,
+            limb_count,
+            sha_precompute_selector,
+            pepper,
+        } => {
+            let (params, public_key) = email.generate_params();
+            let pepper = pepper.map(|p| utils::from_0x_hex(&p).unwrap());
+            let mut options = CircuitOptions::new(target, pepper.unwrap_or_else(|| vec![0; 32]));
+            options.max_header_len = max_header_len;
+            options.limb_bits = limb_bits.unwrap_or(options.limb_bits);
+            options.limb_count = limb_count;
+            options.sha_precompute_selector = sha_precompute_selector;
+            let res = or_exit(email_gen::circuit::generate_inputs(
+                params,
+                &public_key,
+                &options,
+            ));
+            println!("{}", res);
+        }
+        Commands::EmailReply {
+            email,
+            recovery_hash,
+            args_version,
+        } => {
+            let options = email.options();
+            let res = or_exit(email_gen::generate_reply_args(
+                &email.key_file(),
+                email.rsa_bits,
+                email.from,
+                email.to,
+                email.subject,
+                recovery_hash,
+                email.body,
+                email.selector,
+                email.domain,
+                options,
+                args_version,
+            ));
+            println!("{}", res);
+        }
+        Commands::EmailParse { eml, line_endings } => {
+            let raw = std::fs::read(eml).unwrap();
+            let res = email_gen::parse_raw_email(&raw, line_endings);
+            println!("{}", res);
+        }
+        Commands::EmailCommitment {
+            address,
+            pepper,
+            fold_gmail,
+        } => {
+            let res = or_exit(email_gen::commitment::generate_commitment(
+                address, pepper, fold_gmail,
+            ));
+            println!("{}", res);
+        }
+        Commands::Keys { command } => keys_command(command).unwrap(),
+        Commands::Passkey {
+            command:
+                PasskeyCommand::Register {
+                    credential,
+                    force,
+                    passkey,
+                    attestation,
+                },
+        } => {
+            let mut options = passkey.options();
+            options.attestation = attestation;
+            let res = or_exit(passkey_gen::register_credential(credential, force, &options).await);
+            println!("{}", res);
+        }
+        Commands::Passkey {
+            command:
+                PasskeyCommand::Sign {
+                    challenge,
+                    credential,
+                    passkey,
+                    normalize_s,
+                    force_high_s,
+                    args_version,
+                },
+        } => {
+            let mut options = passkey.options();
+            options.signature_s = match (normalize_s, force_high_s) {
+                (true, _) => SignatureS::Low,
+                (_, true) => SignatureS::High,
+                _ => SignatureS::AsSigned,
+            };
+            let res = or_exit(
+                passkey_gen::generate_args(challenge, credential, &options, args_version).await,
+            );
             println!("{}", res);
         }
     }